@@ -7,9 +7,6 @@ use http_body_util::{BodyExt, Empty};
 use hyper::body::Bytes;
 use hyper_rustls::ConfigBuilderExt;
 use hyper_util::{client::legacy::Client, rt::TokioExecutor};
-use rustls::pki_types::pem::PemObject;
-use rustls::pki_types::CertificateDer;
-use rustls::RootCertStore;
 
 use std::str::FromStr;
 use std::{env, io};
@@ -45,19 +42,10 @@ async fn run_client() -> io::Result<()> {
 
     // Prepare the TLS client config
     let tls = match env::args().nth(2) {
-        Some(path) => {
-            // Read trust roots
-            let certs = CertificateDer::pem_file_iter(&path)
-                .and_then(|res| res.collect::<Result<Vec<_>, _>>())
-                .map_err(|err| error(format!("could not read CA store {path}: {err}")))?;
-
-            let mut roots = RootCertStore::empty();
-            roots.add_parsable_certificates(certs);
-            // TLS client config using the custom CA store for lookups
-            rustls::ClientConfig::builder()
-                .with_root_certificates(roots)
-                .with_no_client_auth()
-        }
+        // TLS client config using the custom CA store for lookups
+        Some(path) => rustls::ClientConfig::builder()
+            .with_pem_roots_file(path)?
+            .with_no_client_auth(),
         // Default TLS client config with native roots
         None => rustls::ClientConfig::builder()
             .with_native_roots()?
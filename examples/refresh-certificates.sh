@@ -53,4 +53,35 @@ openssl x509 -req \
             -extensions v3_end -extfile openssl.cnf
 
 cat end.cert inter.cert ca.cert > sample.pem
+
+# an unrelated chain for the same names, used by the pinning tests
+openssl req -nodes \
+          -x509 \
+          -days 3650 \
+          -newkey rsa:2048 \
+          -keyout other-ca.key \
+          -out other-ca.cert \
+          -sha256 \
+          -batch \
+          -subj "/CN=unrelated RSA CA"
+
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout other-end.key \
+          -out other-end.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl x509 -req \
+            -in other-end.req \
+            -out other-end.cert \
+            -CA other-ca.cert \
+            -CAkey other-ca.key \
+            -sha256 \
+            -days 2000 \
+            -set_serial 789 \
+            -extensions v3_end -extfile openssl.cnf
+
+cat other-end.cert other-ca.cert > unrelated.pem
 rm *.key *.cert *.req
@@ -1,25 +1,22 @@
 //! Simple HTTPS echo service based on hyper_util and rustls
 //!
 //! First parameter is the mandatory port to use.
-//! Certificate and private key are hardcoded to sample files.
+//! Certificate and private key are hardcoded to sample files, and TLS is
+//! handled by hyper-rustls' `HttpsAcceptor`.
 //! hyper will automatically use HTTP/2 if a client starts talking HTTP/2,
 //! otherwise HTTP/1.1 will be used.
 
+use std::env;
 use std::net::{Ipv4Addr, SocketAddr};
-use std::sync::Arc;
-use std::{env, io};
 
 use http::{Method, Request, Response, StatusCode};
 use http_body_util::{BodyExt, Full};
 use hyper::body::{Bytes, Incoming};
 use hyper::service::service_fn;
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_rustls::HttpsAcceptorBuilder;
+use hyper_util::rt::TokioExecutor;
 use hyper_util::server::conn::auto::Builder;
-use rustls::pki_types::pem::PemObject;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use rustls::ServerConfig;
 use tokio::net::TcpListener;
-use tokio_rustls::TlsAcceptor;
 
 fn main() {
     // Serve an echo service over HTTPS, with proper error handling.
@@ -29,10 +26,6 @@ fn main() {
     }
 }
 
-fn error(err: String) -> io::Error {
-    io::Error::other(err)
-}
-
 #[tokio::main]
 async fn run_server() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Set a process wide default crypto provider.
@@ -48,44 +41,35 @@ async fn run_server() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     };
     let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
 
-    // Load public certificate.
-    let certs = CertificateDer::pem_file_iter("examples/sample.pem")?
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| error(format!("could not read certificate file: {e}")))?;
-    // Load private key.
-    let key = PrivateKeyDer::from_pem_file("examples/sample.rsa")
-        .map_err(|e| error(format!("could not read private key file: {e}")))?;
+    // Build the TLS acceptor from the sample certificate and private key.
+    let acceptor = HttpsAcceptorBuilder::new()
+        .with_pem_files("examples/sample.pem", "examples/sample.rsa")?
+        .enable_http1();
+    #[cfg(feature = "http2")]
+    let acceptor = acceptor.enable_http2();
+    let acceptor = acceptor.build();
 
-    // Create a TCP listener via tokio.
-    let incoming = TcpListener::bind(&addr).await?;
-    let addr = incoming.local_addr()?;
+    // Create a TCP listener via tokio, and wrap it so that accepted
+    // connections are protected with TLS.
+    let mut incoming = acceptor.listen(TcpListener::bind(&addr).await?);
+    let addr = incoming.get_ref().local_addr()?;
 
     println!("Starting to serve on https://{addr}");
 
-    // Build TLS configuration.
-    let mut server_config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| error(e.to_string()))?;
-    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
-    let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
-
     let service = service_fn(echo);
 
     loop {
-        let (tcp_stream, _remote_addr) = incoming.accept().await?;
+        let (tls_stream, _remote_addr) = match incoming.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                eprintln!("failed to accept connection: {err:#}");
+                continue;
+            }
+        };
 
-        let tls_acceptor = tls_acceptor.clone();
         tokio::spawn(async move {
-            let tls_stream = match tls_acceptor.accept(tcp_stream).await {
-                Ok(tls_stream) => tls_stream,
-                Err(err) => {
-                    eprintln!("failed to perform tls handshake: {err:#}");
-                    return;
-                }
-            };
             if let Err(err) = Builder::new(TokioExecutor::new())
-                .serve_connection(TokioIo::new(tls_stream), service)
+                .serve_connection(tls_stream, service)
                 .await
             {
                 eprintln!("failed to serve connection: {err:#}");
@@ -0,0 +1,395 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+
+use hyper_util::rt::TokioIo;
+use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::server::TlsStream;
+
+pub(crate) mod builder;
+
+/// An acceptor for the `https` scheme.
+///
+/// This performs the server side of TLS handshakes, yielding connections
+/// ready to be served by hyper, for example with
+/// `hyper_util::server::conn::auto::Builder::serve_connection()`.
+#[derive(Clone)]
+pub struct HttpsAcceptor {
+    tls_config: Arc<ServerConfig>,
+    handshake_timeout: Option<Duration>,
+    max_handshakes: NonZeroUsize,
+}
+
+impl HttpsAcceptor {
+    /// Creates a [`crate::HttpsAcceptorBuilder`] to configure a `HttpsAcceptor`.
+    ///
+    /// This is the same as [`crate::HttpsAcceptorBuilder::new()`].
+    pub fn builder() -> builder::AcceptorBuilder<builder::WantsTlsConfig> {
+        builder::AcceptorBuilder::new()
+    }
+
+    /// Creates a new `HttpsAcceptor`.
+    ///
+    /// The recommended way to create a `HttpsAcceptor` is to use a [`crate::HttpsAcceptorBuilder`].
+    /// See [`HttpsAcceptor::builder()`].
+    pub fn new(tls_config: impl Into<Arc<ServerConfig>>) -> Self {
+        Self {
+            tls_config: tls_config.into(),
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            max_handshakes: DEFAULT_MAX_HANDSHAKES,
+        }
+    }
+
+    /// Set a timeout for the TLS handshake.
+    ///
+    /// A handshake that does not complete in time fails with an error of kind
+    /// [`io::ErrorKind::TimedOut`]. Defaults to 10 seconds; passing `None`
+    /// waits indefinitely, letting stalled clients hold on to their connection.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Set the number of handshakes an [`HttpsListener`] runs concurrently.
+    ///
+    /// Once the limit is reached, the listener stops accepting new connections
+    /// until one of the pending handshakes completes. Defaults to 64.
+    pub fn set_max_handshakes(&mut self, max: NonZeroUsize) {
+        self.max_handshakes = max;
+    }
+
+    /// Performs the TLS handshake over an incoming connection.
+    pub async fn accept<IO>(&self, io: IO) -> io::Result<TokioIo<TlsStream<IO>>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let handshake = tokio_rustls::TlsAcceptor::from(self.tls_config.clone()).accept(io);
+        let stream = match self.handshake_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                })??,
+            None => handshake.await?,
+        };
+        Ok(TokioIo::new(stream))
+    }
+
+    /// Wraps a listener, so that accepted connections are protected with TLS.
+    pub fn listen<L: Incoming>(self, incoming: L) -> HttpsListener<L> {
+        HttpsListener {
+            acceptor: self,
+            incoming,
+            handshakes: JoinSet::new(),
+        }
+    }
+
+    /// The TLS configuration used for incoming connections.
+    pub fn tls_config(&self) -> &Arc<ServerConfig> {
+        &self.tls_config
+    }
+}
+
+impl fmt::Debug for HttpsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpsAcceptor")
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("max_handshakes", &self.max_handshakes)
+            .finish_non_exhaustive()
+    }
+}
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_HANDSHAKES: NonZeroUsize = NonZeroUsize::new(64).unwrap();
+
+/// A listener yielding TLS-protected connections.
+///
+/// Handshakes run concurrently on background tasks, so a slow or stalled
+/// client does not hold up others. The number of concurrent handshakes and
+/// their timeout are taken from the [`HttpsAcceptor`].
+///
+/// Created by [`HttpsAcceptor::listen()`].
+pub struct HttpsListener<L: Incoming> {
+    acceptor: HttpsAcceptor,
+    incoming: L,
+    handshakes: JoinSet<Handshake<L>>,
+}
+
+/// The outcome of a handshake, along with the peer's address
+type Handshake<L> = (
+    io::Result<TokioIo<TlsStream<<L as Incoming>::Io>>>,
+    <L as Incoming>::Addr,
+);
+
+impl<L: Incoming> HttpsListener<L> {
+    /// Returns the next connection that completed its TLS handshake.
+    ///
+    /// Failed and timed out handshakes are logged and dropped; only errors
+    /// from the wrapped listener are returned. The listener remains usable
+    /// after an error.
+    ///
+    /// This method is cancel safe: pending handshakes are kept when the
+    /// returned future is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Must be called from within a tokio runtime, as handshakes are spawned
+    /// as tasks.
+    pub async fn accept(&mut self) -> io::Result<(TokioIo<TlsStream<L::Io>>, L::Addr)> {
+        loop {
+            let accepting = self.handshakes.len() < self.acceptor.max_handshakes.get();
+            tokio::select! {
+                res = self.incoming.accept(), if accepting => {
+                    let (io, addr) = res?;
+                    let acceptor = self.acceptor.clone();
+                    self.handshakes
+                        .spawn(async move { (acceptor.accept(io).await, addr) });
+                }
+                Some(res) = self.handshakes.join_next() => match res {
+                    Ok((Ok(stream), addr)) => return Ok((stream, addr)),
+                    Ok((Err(err), addr)) => {
+                        crate::log::debug!("TLS handshake with {addr:?} failed: {err}");
+                    }
+                    Err(err) => match err.try_into_panic() {
+                        Ok(panic) => std::panic::resume_unwind(panic),
+                        Err(err) => crate::log::debug!("TLS handshake task failed: {err}"),
+                    },
+                },
+            }
+        }
+    }
+
+    /// The number of handshakes currently in progress.
+    pub fn pending_handshakes(&self) -> usize {
+        self.handshakes.len()
+    }
+
+    /// Returns a reference to the wrapped listener.
+    pub fn get_ref(&self) -> &L {
+        &self.incoming
+    }
+
+    /// The acceptor performing TLS handshakes.
+    pub fn acceptor(&self) -> &HttpsAcceptor {
+        &self.acceptor
+    }
+}
+
+/// A source of incoming connections, such as a [`TcpListener`].
+pub trait Incoming {
+    /// The type of an incoming connection.
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    /// The address of the remote peer.
+    type Addr: fmt::Debug + Send + 'static;
+
+    /// Accepts the next incoming connection.
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send;
+}
+
+impl Incoming for TcpListener {
+    type Io = tokio::net::TcpStream;
+    type Addr = std::net::SocketAddr;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send {
+        Self::accept(self)
+    }
+}
+
+#[cfg(unix)]
+impl Incoming for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+    type Addr = tokio::net::unix::SocketAddr;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send {
+        Self::accept(self)
+    }
+}
+
+#[cfg(all(test, feature = "http1", any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use http::{Request, Response};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::{Bytes, Incoming as IncomingBody};
+    use hyper::service::service_fn;
+    use hyper_util::client::legacy::Client;
+    use hyper_util::rt::TokioExecutor;
+    use hyper_util::server::conn::auto;
+
+    use super::builder::{AcceptorBuilder, WantsProtocols1};
+    use super::*;
+    use crate::test_util::trusting_config;
+    use crate::{HttpsAcceptorBuilder, HttpsConnectorBuilder, TlsInfo};
+
+    #[tokio::test]
+    async fn serves_https() {
+        let addr = serve(sample_acceptor().enable_http1().build()).await;
+
+        let https = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build(https);
+        let res = client
+            .get(
+                format!("https://localhost:{}/", addr.port())
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let info = res
+            .extensions()
+            .get::<TlsInfo>()
+            .unwrap()
+            .clone();
+        assert_eq!(info.alpn_protocol(), None);
+
+        let body = res
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn listener_survives_failed_handshake() {
+        let mut listener = listener(sample_acceptor().enable_http1().build()).await;
+        let addr = listener.get_ref().local_addr().unwrap();
+
+        // A client that gives up immediately
+        drop(
+            tokio::net::TcpStream::connect(addr)
+                .await
+                .unwrap(),
+        );
+
+        let client = tls_client(addr);
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, client.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stalled_client_does_not_block_others() {
+        let mut listener = listener(
+            sample_acceptor()
+                .with_handshake_timeout(None)
+                .enable_http1()
+                .build(),
+        )
+        .await;
+        let addr = listener.get_ref().local_addr().unwrap();
+
+        let _stalled = tokio::net::TcpStream::connect(addr)
+            .await
+            .unwrap();
+        let client = tls_client(addr);
+        let (_, peer) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(peer, client.await.unwrap());
+        assert_eq!(listener.pending_handshakes(), 1);
+    }
+
+    #[tokio::test]
+    async fn handshake_cap_and_timeout() {
+        let mut listener = listener(
+            sample_acceptor()
+                .with_max_handshakes(NonZeroUsize::MIN)
+                .with_handshake_timeout(Some(Duration::from_millis(200)))
+                .enable_http1()
+                .build(),
+        )
+        .await;
+        let addr = listener.get_ref().local_addr().unwrap();
+
+        // The stalled client takes the only handshake slot until it times out
+        let _stalled = tokio::net::TcpStream::connect(addr)
+            .await
+            .unwrap();
+        let client = tls_client(addr);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), listener.accept())
+                .await
+                .is_err()
+        );
+        assert_eq!(listener.pending_handshakes(), 1);
+
+        let (_, peer) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(peer, client.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn accept_times_out() {
+        let acceptor = sample_acceptor()
+            .with_handshake_timeout(Some(Duration::from_millis(50)))
+            .enable_http1()
+            .build();
+        let (_client, server) = tokio::io::duplex(1024);
+        let err = acceptor
+            .accept(server)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    fn sample_acceptor() -> AcceptorBuilder<WantsProtocols1> {
+        HttpsAcceptorBuilder::new()
+            .with_pem_files("examples/sample.pem", "examples/sample.rsa")
+            .unwrap()
+    }
+
+    async fn listener(acceptor: HttpsAcceptor) -> HttpsListener<TcpListener> {
+        acceptor.listen(
+            TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap(),
+        )
+    }
+
+    /// Completes a TLS handshake with the server at `addr`, returning the client's address
+    fn tls_client(addr: SocketAddr) -> tokio::task::JoinHandle<SocketAddr> {
+        tokio::spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr)
+                .await
+                .unwrap();
+            let local = stream.local_addr().unwrap();
+            tokio_rustls::TlsConnector::from(Arc::new(trusting_config()))
+                .connect("localhost".try_into().unwrap(), stream)
+                .await
+                .unwrap();
+            local
+        })
+    }
+
+    async fn serve(acceptor: HttpsAcceptor) -> SocketAddr {
+        let mut listener = listener(acceptor).await;
+        let addr = listener.get_ref().local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let service = service_fn(|_: Request<IncomingBody>| async {
+                        Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from("hello"))))
+                    });
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(stream, service)
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+}
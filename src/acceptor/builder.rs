@@ -0,0 +1,328 @@
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+use std::io;
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+use std::path::Path;
+
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+use rustls::pki_types::pem::PemObject;
+#[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::ServerConfig;
+
+use super::{HttpsAcceptor, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_HANDSHAKES};
+use crate::error::BuilderError;
+
+/// A builder for an [`HttpsAcceptor`]
+///
+/// This makes configuration flexible and explicit and ensures acceptor
+/// features match crate features
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper_rustls::HttpsAcceptorBuilder;
+///
+/// # #[cfg(all(feature = "http1", feature = "aws-lc-rs"))]
+/// # async fn run() -> std::io::Result<()> {
+/// # let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+/// let acceptor = HttpsAcceptorBuilder::new()
+///     .with_pem_files("cert.pem", "key.pem")?
+///     .enable_http1()
+///     .build();
+/// let mut listener = acceptor.listen(tokio::net::TcpListener::bind("[::]:443").await?);
+/// let (stream, remote_addr) = listener.accept().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AcceptorBuilder<State>(State);
+
+/// State of a builder that needs a TLS server config next
+pub struct WantsTlsConfig(());
+
+impl AcceptorBuilder<WantsTlsConfig> {
+    /// Creates a new [`AcceptorBuilder`]
+    pub fn new() -> Self {
+        Self(WantsTlsConfig(()))
+    }
+
+    /// Passes a rustls [`ServerConfig`] to configure the TLS connection
+    ///
+    /// The [`alpn_protocols`](ServerConfig::alpn_protocols) field is
+    /// required to be empty, or [`BuilderError::PresetAlpn`] is returned. It
+    /// will be rewritten to match the enabled protocols (see
+    /// [`enable_http1`](AcceptorBuilder::enable_http1),
+    /// [`enable_http2`](AcceptorBuilder::enable_http2)) before the
+    /// acceptor is built.
+    pub fn try_with_tls_config(
+        self,
+        config: ServerConfig,
+    ) -> Result<AcceptorBuilder<WantsProtocols1>, BuilderError> {
+        if !config.alpn_protocols.is_empty() {
+            return Err(BuilderError::PresetAlpn(config.alpn_protocols));
+        }
+        Ok(self.with_checked_tls_config(config))
+    }
+
+    /// Passes a [`ServerConfig`] that has no ALPN protocols yet
+    fn with_checked_tls_config(self, config: ServerConfig) -> AcceptorBuilder<WantsProtocols1> {
+        AcceptorBuilder(WantsProtocols1 {
+            tls_config: config,
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            max_handshakes: DEFAULT_MAX_HANDSHAKES,
+        })
+    }
+
+    /// Shorthand for using rustls' default crypto provider and other defaults,
+    /// no client authentication, and a single certificate chain and private key
+    ///
+    /// See [`ConfigBuilder::with_single_cert()`](rustls::ConfigBuilder::with_single_cert).
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    pub fn with_single_cert(
+        self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key_der: PrivateKeyDer<'static>,
+    ) -> Result<AcceptorBuilder<WantsProtocols1>, rustls::Error> {
+        Ok(self.with_checked_tls_config(
+            ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(cert_chain, key_der)?,
+        ))
+    }
+
+    /// Shorthand for [`with_single_cert()`](Self::with_single_cert), loading the
+    /// certificate chain and private key from PEM files
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    pub fn with_pem_files(
+        self,
+        cert_chain: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> io::Result<AcceptorBuilder<WantsProtocols1>> {
+        let cert_chain = cert_chain.as_ref();
+        let certs = CertificateDer::pem_file_iter(cert_chain)
+            .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| {
+                io::Error::other(format!(
+                    "could not read certificate file {}: {e}",
+                    cert_chain.display()
+                ))
+            })?;
+
+        let key = key.as_ref();
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| {
+            io::Error::other(format!(
+                "could not read private key file {}: {e}",
+                key.display()
+            ))
+        })?;
+
+        self.with_single_cert(certs, key)
+            .map_err(io::Error::other)
+    }
+}
+
+impl Default for AcceptorBuilder<WantsTlsConfig> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// State of a builder that needs to have some protocols (HTTP1 or later)
+/// enabled next
+///
+/// No protocol has been enabled at this point.
+pub struct WantsProtocols1 {
+    tls_config: ServerConfig,
+    handshake_timeout: Option<Duration>,
+    max_handshakes: NonZeroUsize,
+}
+
+impl WantsProtocols1 {
+    fn build(self) -> HttpsAcceptor {
+        HttpsAcceptor {
+            tls_config: Arc::new(self.tls_config),
+            handshake_timeout: self.handshake_timeout,
+            max_handshakes: self.max_handshakes,
+        }
+    }
+}
+
+impl AcceptorBuilder<WantsProtocols1> {
+    /// Set a timeout for the TLS handshake
+    ///
+    /// Handshakes that take longer than `timeout` are aborted. Defaults to
+    /// 10 seconds; passing `None` waits indefinitely.
+    ///
+    /// See [`HttpsAcceptor::set_handshake_timeout()`].
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.0.handshake_timeout = timeout;
+        self
+    }
+
+    /// Limit the number of TLS handshakes an [`HttpsListener`](crate::HttpsListener)
+    /// runs concurrently
+    ///
+    /// Defaults to 64. See [`HttpsAcceptor::set_max_handshakes()`].
+    pub fn with_max_handshakes(mut self, max: NonZeroUsize) -> Self {
+        self.0.max_handshakes = max;
+        self
+    }
+
+    /// Enable HTTP1
+    ///
+    /// This needs to be called explicitly, no protocol is enabled by default
+    #[cfg(feature = "http1")]
+    pub fn enable_http1(mut self) -> AcceptorBuilder<WantsProtocols2> {
+        self.0.tls_config.alpn_protocols = vec![b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+        AcceptorBuilder(WantsProtocols2 { inner: self.0 })
+    }
+
+    /// Enable HTTP2
+    ///
+    /// This needs to be called explicitly, no protocol is enabled by default
+    #[cfg(feature = "http2")]
+    pub fn enable_http2(mut self) -> AcceptorBuilder<WantsProtocols3> {
+        self.0.tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        AcceptorBuilder(WantsProtocols3 { inner: self.0 })
+    }
+
+    /// Enable all HTTP versions built into this library (enabled with Cargo features)
+    ///
+    /// For now, this could enable both HTTP 1 and 2, depending on active features.
+    /// In the future, other supported versions will be enabled as well.
+    #[cfg(feature = "http2")]
+    pub fn enable_all_versions(mut self) -> AcceptorBuilder<WantsProtocols3> {
+        #[cfg(feature = "http1")]
+        let alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+        #[cfg(not(feature = "http1"))]
+        let alpn_protocols = vec![b"h2".to_vec()];
+
+        self.0.tls_config.alpn_protocols = alpn_protocols;
+        AcceptorBuilder(WantsProtocols3 { inner: self.0 })
+    }
+}
+
+/// State of a builder with HTTP1 enabled, that may have some other
+/// protocols (HTTP2 or later) enabled next
+///
+/// At this point an acceptor can be built, see
+/// [`build`](AcceptorBuilder<WantsProtocols2>::build).
+pub struct WantsProtocols2 {
+    inner: WantsProtocols1,
+}
+
+impl AcceptorBuilder<WantsProtocols2> {
+    /// Enable HTTP2
+    ///
+    /// This needs to be called explicitly, no protocol is enabled by default
+    #[cfg(feature = "http2")]
+    pub fn enable_http2(mut self) -> AcceptorBuilder<WantsProtocols3> {
+        self.0.inner.tls_config.alpn_protocols =
+            vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+        AcceptorBuilder(WantsProtocols3 {
+            inner: self.0.inner,
+        })
+    }
+
+    /// This builds an [`HttpsAcceptor`]
+    pub fn build(self) -> HttpsAcceptor {
+        self.0.inner.build()
+    }
+}
+
+/// State of a builder with HTTP2 (and possibly HTTP1) enabled
+///
+/// At this point an acceptor can be built, see
+/// [`build`](AcceptorBuilder<WantsProtocols3>::build).
+#[cfg(feature = "http2")]
+pub struct WantsProtocols3 {
+    inner: WantsProtocols1,
+}
+
+#[cfg(feature = "http2")]
+impl AcceptorBuilder<WantsProtocols3> {
+    /// This builds an [`HttpsAcceptor`]
+    pub fn build(self) -> HttpsAcceptor {
+        self.0.inner.build()
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    #[cfg(feature = "http1")]
+    use crate::test_util::{sample_certs, sample_key};
+
+    #[test]
+    #[cfg(feature = "http1")]
+    fn test_builder() {
+        let acceptor = super::AcceptorBuilder::new()
+            .with_pem_files("examples/sample.pem", "examples/sample.rsa")
+            .unwrap()
+            .enable_http1()
+            .build();
+        assert_eq!(
+            &acceptor.tls_config().alpn_protocols,
+            &[b"http/1.1".to_vec(), b"http/1.0".to_vec()]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "http1")]
+    fn test_missing_pem_file() {
+        let err = super::AcceptorBuilder::new()
+            .with_pem_files("examples/missing.pem", "examples/sample.rsa")
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("could not read certificate file examples/missing.pem"));
+    }
+
+    #[test]
+    #[cfg(feature = "http1")]
+    fn test_reject_predefined_alpn() {
+        let mut config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(sample_certs(), sample_key())
+            .unwrap();
+        config.alpn_protocols = vec![b"fancyprotocol".to_vec()];
+        let result = super::AcceptorBuilder::new().try_with_tls_config(config);
+        assert!(matches!(
+            result,
+            Err(super::BuilderError::PresetAlpn(protocols)) if protocols == [b"fancyprotocol"]
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn test_alpn() {
+        let acceptor = super::AcceptorBuilder::new()
+            .with_single_cert(sample_certs(), sample_key())
+            .unwrap()
+            .enable_http2()
+            .build();
+        assert_eq!(&acceptor.tls_config().alpn_protocols, &[b"h2".to_vec()]);
+        let acceptor = super::AcceptorBuilder::new()
+            .with_single_cert(sample_certs(), sample_key())
+            .unwrap()
+            .enable_http1()
+            .enable_http2()
+            .build();
+        assert_eq!(
+            &acceptor.tls_config().alpn_protocols,
+            &[b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()]
+        );
+        let acceptor = super::AcceptorBuilder::new()
+            .with_single_cert(sample_certs(), sample_key())
+            .unwrap()
+            .enable_all_versions()
+            .build();
+        assert_eq!(
+            &acceptor.tls_config().alpn_protocols,
+            &[b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()]
+        );
+    }
+}
@@ -1,18 +1,23 @@
-#[cfg(feature = "rustls-native-certs")]
-use std::io;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
-#[cfg(any(
-    feature = "rustls-platform-verifier",
-    feature = "rustls-native-certs",
-    feature = "webpki-roots"
-))]
 use rustls::client::WantsClientCert;
-use rustls::{ClientConfig, ConfigBuilder, WantsVerifier};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig, ConfigBuilder, RootCertStore, WantsVerifier};
+
+use self::env::EnvRoots;
 #[cfg(feature = "rustls-native-certs")]
 use rustls_native_certs::CertificateResult;
 #[cfg(feature = "rustls-platform-verifier")]
 use rustls_platform_verifier::BuilderVerifierExt;
 
+pub(crate) mod env;
+pub(crate) mod identity;
+pub(crate) mod pinning;
+pub(crate) mod roots;
+
 /// Methods for configuring roots
 ///
 /// This adds methods (gated by crate features) for easily configuring
@@ -54,6 +59,69 @@ pub trait ConfigBuilderExt: sealed::Sealed {
     /// trusted roots as packaged by webpki-roots.
     #[cfg(feature = "webpki-roots")]
     fn with_webpki_roots(self) -> ConfigBuilder<ClientConfig, WantsClientCert>;
+
+    /// This configures the certificates in a PEM file as trusted roots
+    ///
+    /// Certificates that rustls rejects are skipped, but reading stops at the
+    /// first malformed PEM section, as the parser cannot reliably resume after
+    /// it. This will return an error if the file cannot be read, or if no
+    /// valid certs were found.
+    fn with_pem_roots_file(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error>;
+
+    /// This configures the certificates in all PEM files of a directory as
+    /// trusted roots
+    ///
+    /// This also handles OpenSSL hashed directories (as prepared by
+    /// `openssl rehash` or `c_rehash`), where each certificate is reachable
+    /// through both its own file and a symlink named after its subject hash:
+    /// every file is only read once. Subdirectories and unreadable files are
+    /// skipped, and each file is read as in
+    /// [`with_pem_roots_file()`](Self::with_pem_roots_file).
+    ///
+    /// This will return an error if the directory cannot be read, or if no
+    /// valid certs were found.
+    fn with_pem_roots_dir(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error>;
+
+    /// This configures the certificates in a PEM-encoded buffer as trusted roots
+    ///
+    /// The buffer is read as in
+    /// [`with_pem_roots_file()`](Self::with_pem_roots_file). This will return
+    /// an error if no valid certs were found.
+    fn with_pem_roots_bytes(
+        self,
+        pem: &[u8],
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error>;
+
+    /// This configures the roots named by the `SSL_CERT_FILE` and
+    /// `SSL_CERT_DIR` environment variables, or the `fallback` roots if
+    /// neither is set
+    ///
+    /// The variables are read the same way on every platform; see [`EnvRoots`]
+    /// for details. Every loaded or rejected file is logged. This will return
+    /// an error if the variables are set but no valid certs were found, or if
+    /// `fallback` fails.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "webpki-roots")]
+    /// # fn doc() -> std::io::Result<()> {
+    /// use hyper_rustls::{ConfigBuilderExt, RootsBuilder};
+    ///
+    /// let config = rustls::ClientConfig::builder()
+    ///     .with_env_roots(|| RootsBuilder::new().with_webpki_roots().build())?
+    ///     .with_no_client_auth();
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn with_env_roots(
+        self,
+        fallback: impl FnOnce() -> Result<RootCertStore, io::Error>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error>;
 }
 
 impl ConfigBuilderExt for ConfigBuilder<ClientConfig, WantsVerifier> {
@@ -71,58 +139,217 @@ impl ConfigBuilderExt for ConfigBuilder<ClientConfig, WantsVerifier> {
     }
 
     #[cfg(feature = "rustls-native-certs")]
-    #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
     fn with_native_roots(self) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error> {
-        let mut roots = rustls::RootCertStore::empty();
-        let mut valid_count = 0;
-        let mut invalid_count = 0;
+        Ok(self.with_root_certificates(native_roots()?))
+    }
 
-        let CertificateResult { certs, errors, .. } = rustls_native_certs::load_native_certs();
-        if !errors.is_empty() {
-            crate::log::warn!("native root CA certificate loading errors: {errors:?}");
+    #[cfg(feature = "webpki-roots")]
+    fn with_webpki_roots(self) -> ConfigBuilder<ClientConfig, WantsClientCert> {
+        self.with_root_certificates(webpki_roots())
+    }
+
+    fn with_pem_roots_file(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error> {
+        Ok(self.with_root_certificates(pem_file_roots(path.as_ref())?))
+    }
+
+    fn with_pem_roots_dir(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error> {
+        Ok(self.with_root_certificates(pem_dir_roots(path.as_ref())?))
+    }
+
+    fn with_pem_roots_bytes(
+        self,
+        pem: &[u8],
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error> {
+        Ok(self.with_root_certificates(pem_bytes_roots(pem)?))
+    }
+
+    fn with_env_roots(
+        self,
+        fallback: impl FnOnce() -> Result<RootCertStore, io::Error>,
+    ) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>, io::Error> {
+        Ok(self.with_root_certificates(env_roots(fallback)?))
+    }
+}
+
+/// Loads the roots named by `SSL_CERT_FILE` and `SSL_CERT_DIR`, or the `fallback` roots
+pub(crate) fn env_roots(
+    fallback: impl FnOnce() -> Result<RootCertStore, io::Error>,
+) -> Result<RootCertStore, io::Error> {
+    match EnvRoots::load() {
+        Some(roots) => roots.into_roots(),
+        None => fallback(),
+    }
+}
+
+/// Loads the platform's trusted certs, as implemented by rustls-native-certs
+#[cfg(feature = "rustls-native-certs")]
+pub(crate) fn native_roots() -> Result<RootCertStore, io::Error> {
+    let CertificateResult { certs, errors, .. } = rustls_native_certs::load_native_certs();
+    if !errors.is_empty() {
+        crate::log::warn!("native root CA certificate loading errors: {errors:?}");
+    }
+
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no native root CA certificates found (errors: {errors:?})"),
+        ));
+    }
+
+    root_store(
+        "with_native_roots",
+        "native root CA certificates",
+        certs.into_iter().map(Ok),
+    )
+}
+
+/// Returns Mozilla's set of trusted roots as packaged by webpki-roots
+#[cfg(feature = "webpki-roots")]
+pub(crate) fn webpki_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.extend(
+        webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .cloned(),
+    );
+    roots
+}
+
+/// Loads the certificates in a PEM file
+pub(crate) fn pem_file_roots(path: &Path) -> Result<RootCertStore, io::Error> {
+    let certs = CertificateDer::pem_file_iter(path).map_err(|err| pem_error(path, err))?;
+    root_store(
+        "with_pem_roots_file",
+        &format!("root CA certificates in {}", path.display()),
+        pem_certs(certs),
+    )
+}
+
+/// Loads the certificates in all PEM files of a directory
+pub(crate) fn pem_dir_roots(path: &Path) -> Result<RootCertStore, io::Error> {
+    let context = |err: io::Error| {
+        io::Error::new(
+            err.kind(),
+            format!("could not read directory {}: {err}", path.display()),
+        )
+    };
+
+    let files = pem_dir_files(path).map_err(context)?;
+    let mut certs = Vec::new();
+    for file in files {
+        match CertificateDer::pem_file_iter(&file) {
+            Ok(iter) => certs.extend(pem_certs(iter)),
+            Err(err) => crate::log::debug!("skipping {}: {err}", file.display()),
         }
+    }
 
-        if certs.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("no native root CA certificates found (errors: {errors:?})"),
-            ));
+    root_store(
+        "with_pem_roots_dir",
+        &format!("root CA certificates in {}", path.display()),
+        certs,
+    )
+}
+
+/// Lists the regular files in a directory, resolving symlinks
+///
+/// This deduplicates the entries of OpenSSL hashed directories.
+fn pem_dir_files(path: &Path) -> Result<BTreeSet<PathBuf>, io::Error> {
+    let mut files = BTreeSet::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        match fs::canonicalize(entry.path()) {
+            Ok(file) if file.is_file() => {
+                files.insert(file);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                crate::log::debug!("skipping {}: {err}", entry.path().display());
+            }
         }
+    }
+    Ok(files)
+}
+
+/// Loads the certificates in a PEM-encoded buffer
+pub(crate) fn pem_bytes_roots(pem: &[u8]) -> Result<RootCertStore, io::Error> {
+    root_store(
+        "with_pem_roots_bytes",
+        "PEM root CA certificates",
+        pem_certs(CertificateDer::pem_slice_iter(pem)),
+    )
+}
 
-        for cert in certs {
-            match roots.add(cert) {
+/// Builds a root store from `certs`, counting valid and invalid certificates
+///
+/// Fails if no valid certificate was found, using `description` to name the
+/// expected certificates in the error.
+fn root_store(
+    method: &str,
+    description: &str,
+    certs: impl IntoIterator<Item = Result<CertificateDer<'static>, pem::Error>>,
+) -> Result<RootCertStore, io::Error> {
+    let mut roots = RootCertStore::empty();
+    let mut valid_count = 0;
+    let mut invalid_count = 0;
+
+    for cert in certs {
+        match cert {
+            Ok(cert) => match roots.add(cert) {
                 Ok(_) => valid_count += 1,
                 Err(err) => {
                     crate::log::debug!("certificate parsing failed: {err:?}");
                     invalid_count += 1
                 }
+            },
+            Err(err) => {
+                crate::log::debug!("PEM decoding failed: {err:?}");
+                invalid_count += 1
             }
         }
-
-        crate::log::debug!(
-            "with_native_roots processed {valid_count} valid and {invalid_count} invalid certs"
-        );
-        if roots.is_empty() {
-            crate::log::debug!("no valid native root CA certificates found");
-            Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("no valid native root CA certificates found ({invalid_count} invalid)"),
-            ))?
-        }
-
-        Ok(self.with_root_certificates(roots))
     }
 
-    #[cfg(feature = "webpki-roots")]
-    fn with_webpki_roots(self) -> ConfigBuilder<ClientConfig, WantsClientCert> {
-        let mut roots = rustls::RootCertStore::empty();
-        roots.extend(
-            webpki_roots::TLS_SERVER_ROOTS
-                .iter()
-                .cloned(),
-        );
-        self.with_root_certificates(roots)
+    crate::log::debug!("{method} processed {valid_count} valid and {invalid_count} invalid certs");
+    if roots.is_empty() {
+        crate::log::debug!("no valid {description} found");
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no valid {description} found ({invalid_count} invalid)"),
+        ));
     }
+
+    Ok(roots)
+}
+
+/// Yields the certificates of a PEM source, stopping after the first decoding error
+///
+/// The PEM parser cannot reliably resume after a malformed section.
+fn pem_certs<T>(iter: T) -> impl Iterator<Item = Result<CertificateDer<'static>, pem::Error>>
+where
+    T: Iterator<Item = Result<CertificateDer<'static>, pem::Error>>,
+{
+    let mut failed = false;
+    iter.take_while(move |cert| {
+        let take = !failed;
+        failed |= cert.is_err();
+        take
+    })
+}
+
+fn pem_error(path: &Path, err: pem::Error) -> io::Error {
+    let kind = match &err {
+        pem::Error::Io(err) => err.kind(),
+        _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(
+        kind,
+        format!("could not read certificate file {}: {err}", path.display()),
+    )
 }
 
 mod sealed {
@@ -133,3 +360,96 @@ mod sealed {
 
     impl Sealed for ConfigBuilder<ClientConfig, WantsVerifier> {}
 }
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const SAMPLE: &[u8] = include_bytes!("../examples/sample.pem");
+
+    #[test]
+    fn pem_roots_file() {
+        ClientConfig::builder()
+            .with_pem_roots_file("examples/sample.pem")
+            .unwrap();
+
+        let err = ClientConfig::builder()
+            .with_pem_roots_file("examples/missing.pem")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .starts_with("could not read certificate file examples/missing.pem"));
+
+        // The private key is not a certificate
+        let err = ClientConfig::builder()
+            .with_pem_roots_file("examples/sample.rsa")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "no valid root CA certificates in examples/sample.rsa found (0 invalid)"
+        );
+    }
+
+    #[test]
+    fn pem_roots_bytes() {
+        ClientConfig::builder()
+            .with_pem_roots_bytes(SAMPLE)
+            .unwrap();
+
+        let err = ClientConfig::builder()
+            .with_pem_roots_bytes(b"-----BEGIN CERTIFICATE-----\nnot base64!\n")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "no valid PEM root CA certificates found (1 invalid)"
+        );
+    }
+
+    #[test]
+    fn pem_roots_dir() {
+        let dir = TempDir::new("pem_roots_dir");
+        fs::write(dir.path().join("sample.pem"), SAMPLE).unwrap();
+        fs::write(dir.path().join("README"), b"not a certificate").unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sample.pem", dir.path().join("0123abcd.0")).unwrap();
+
+        ClientConfig::builder()
+            .with_pem_roots_dir(dir.path())
+            .unwrap();
+
+        fs::remove_file(dir.path().join("sample.pem")).unwrap();
+        let err = ClientConfig::builder()
+            .with_pem_roots_dir(dir.path())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn pem_roots_dir_missing() {
+        let err = ClientConfig::builder()
+            .with_pem_roots_dir("examples/missing")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .starts_with("could not read directory examples/missing"));
+    }
+
+    #[test]
+    fn pem_certs_stops_at_first_error() {
+        let mut pem =
+            b"-----BEGIN CERTIFICATE-----\nnot base64!\n-----END CERTIFICATE-----\n".to_vec();
+        pem.extend_from_slice(SAMPLE);
+        let certs = pem_certs(CertificateDer::pem_slice_iter(&pem)).collect::<Vec<_>>();
+        assert_eq!(certs.len(), 1);
+        assert!(certs[0].is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+
+use rustls::RootCertStore;
+
+/// Root certificates named by the `SSL_CERT_FILE` and `SSL_CERT_DIR`
+/// environment variables
+///
+/// These variables are read the same way on every platform, following
+/// OpenSSL's conventions: `SSL_CERT_FILE` names a PEM file, and
+/// `SSL_CERT_DIR` a list of directories of PEM files, separated like the
+/// entries of `PATH` (see [`std::env::split_paths()`]). Empty variables are
+/// ignored.
+///
+/// Each file is loaded on its own, and reported as either loaded or rejected.
+/// Use [`ConfigBuilderExt::with_env_roots()`](crate::ConfigBuilderExt::with_env_roots)
+/// to configure these roots with a fallback.
+#[derive(Debug)]
+pub struct EnvRoots {
+    roots: RootCertStore,
+    loaded: Vec<PathBuf>,
+    rejected: Vec<(PathBuf, io::Error)>,
+}
+
+impl EnvRoots {
+    /// Loads the roots named by `SSL_CERT_FILE` and `SSL_CERT_DIR`
+    ///
+    /// Returns `None` if neither variable is set.
+    pub fn load() -> Option<Self> {
+        Self::from_vars(env::var_os("SSL_CERT_FILE"), env::var_os("SSL_CERT_DIR"))
+    }
+
+    fn from_vars(file: Option<OsString>, dirs: Option<OsString>) -> Option<Self> {
+        let file = file.filter(|file| !file.is_empty());
+        let dirs = dirs.filter(|dirs| !dirs.is_empty());
+        if file.is_none() && dirs.is_none() {
+            return None;
+        }
+
+        let mut this = Self {
+            roots: RootCertStore::empty(),
+            loaded: Vec::new(),
+            rejected: Vec::new(),
+        };
+        if let Some(file) = file {
+            this.add_file(PathBuf::from(file));
+        }
+        for dir in dirs.iter().flat_map(env::split_paths) {
+            match super::pem_dir_files(&dir) {
+                Ok(files) => files
+                    .into_iter()
+                    .for_each(|file| this.add_file(file)),
+                Err(err) => this.reject(dir, err),
+            }
+        }
+
+        crate::log::debug!(
+            "loaded {} roots from {} files named by SSL_CERT_FILE and SSL_CERT_DIR, rejected {}",
+            this.roots.len(),
+            this.loaded.len(),
+            this.rejected.len()
+        );
+        Some(this)
+    }
+
+    fn add_file(&mut self, path: PathBuf) {
+        if self.loaded.contains(&path)
+            || self
+                .rejected
+                .iter()
+                .any(|(rejected, _)| rejected == &path)
+        {
+            return;
+        }
+
+        match super::pem_file_roots(&path) {
+            Ok(store) => {
+                crate::log::debug!("loaded {} roots from {}", store.len(), path.display());
+                self.roots.roots.extend(store.roots);
+                self.loaded.push(path);
+            }
+            Err(err) => self.reject(path, err),
+        }
+    }
+
+    fn reject(&mut self, path: PathBuf, err: io::Error) {
+        crate::log::warn!("rejected {}: {err}", path.display());
+        self.rejected.push((path, err));
+    }
+
+    /// The roots loaded from all accepted files
+    pub fn roots(&self) -> &RootCertStore {
+        &self.roots
+    }
+
+    /// The files roots were loaded from
+    pub fn loaded_files(&self) -> &[PathBuf] {
+        &self.loaded
+    }
+
+    /// The files and directories that could not be read or contained no
+    /// valid certificates, along with the reason
+    pub fn rejected_files(&self) -> &[(PathBuf, io::Error)] {
+        &self.rejected
+    }
+
+    /// Returns the loaded roots, failing if there are none
+    pub(crate) fn into_roots(self) -> Result<RootCertStore, io::Error> {
+        if !self.roots.is_empty() {
+            return Ok(self.roots);
+        }
+
+        let rejected = self
+            .rejected
+            .iter()
+            .map(|(path, err)| format!("{}: {err}", path.display()))
+            .collect::<Vec<_>>();
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no valid root CA certificates found through SSL_CERT_FILE or SSL_CERT_DIR (rejected: [{}])",
+                rejected.join(", ")
+            ),
+        ))
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn unset_or_empty() {
+        assert!(EnvRoots::from_vars(None, None).is_none());
+        assert!(EnvRoots::from_vars(Some("".into()), Some("".into())).is_none());
+    }
+
+    #[test]
+    fn file_and_dirs() {
+        let temp = TempDir::new("env-roots");
+        let dir = temp.path();
+        fs::copy("examples/sample.pem", dir.join("sample.pem")).unwrap();
+        fs::write(dir.join("README"), b"not a certificate").unwrap();
+        let missing = dir.join("missing");
+        let dirs = env::join_paths([dir, missing.as_path()]).unwrap();
+
+        let roots = EnvRoots::from_vars(Some("examples/sample.pem".into()), Some(dirs)).unwrap();
+        let canonical = fs::canonicalize(dir).unwrap();
+        assert_eq!(roots.roots().len(), 6);
+        assert_eq!(
+            roots.loaded_files(),
+            &[
+                PathBuf::from("examples/sample.pem"),
+                canonical.join("sample.pem")
+            ]
+        );
+        let rejected = roots
+            .rejected_files()
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(rejected, &[canonical.join("README"), missing]);
+        assert!(roots.into_roots().is_ok());
+    }
+
+    #[test]
+    fn no_valid_roots() {
+        let roots = EnvRoots::from_vars(Some("examples/sample.rsa".into()), None).unwrap();
+        assert!(roots.loaded_files().is_empty());
+        let err = roots.into_roots().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .contains("(rejected: [examples/sample.rsa: no valid root CA certificates in"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::io;
+use std::path::Path;
+
+use rustls::client::WantsClientCert;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, ConfigBuilder, InconsistentKeys};
+
+/// A client certificate chain and its private key, for mutual TLS
+///
+/// The chain starts with the end-entity certificate, optionally followed by
+/// intermediates. The key can be in PKCS#8, PKCS#1 or SEC1 format.
+///
+/// Whether the key matches the certificate is checked when the identity is
+/// used to build a configuration, see for example
+/// [`ConnectorBuilder::with_provider_roots_and_client_auth()`](crate::HttpsConnectorBuilder::with_provider_roots_and_client_auth).
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(all(feature = "rustls-native-certs", feature = "http1"))]
+/// # fn doc() -> std::io::Result<()> {
+/// use hyper_rustls::{ClientIdentity, HttpsConnectorBuilder};
+///
+/// let identity = ClientIdentity::from_pem_files("client.pem", "client.key")?;
+/// let https = HttpsConnectorBuilder::new()
+///     .with_native_roots_and_client_auth(identity)?
+///     .https_only()
+///     .enable_http1()
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ClientIdentity {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl ClientIdentity {
+    /// Creates an identity from a certificate chain and its private key
+    pub fn new(cert_chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        Self { cert_chain, key }
+    }
+
+    /// Loads an identity from a PEM file with the certificate chain and a
+    /// PEM file with the private key
+    ///
+    /// Both may be the same file. This fails if either file cannot be read,
+    /// or contains no certificate or key respectively.
+    pub fn from_pem_files(
+        cert_chain: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Self, io::Error> {
+        let (cert_path, key_path) = (cert_chain.as_ref(), key.as_ref());
+        let cert_chain = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| pem_error("certificate chain", cert_path.display(), err))?;
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|err| pem_error("private key", key_path.display(), err))?;
+        Self::checked(cert_chain, key, cert_path.display())
+    }
+
+    /// Loads an identity from PEM-encoded buffers with the certificate chain
+    /// and the private key
+    ///
+    /// Both may be the same buffer.
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self, io::Error> {
+        let cert_chain = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| pem_error("certificate chain", "PEM buffer", err))?;
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|err| pem_error("private key", "PEM buffer", err))?;
+        Self::checked(cert_chain, key, "PEM buffer")
+    }
+
+    fn checked(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        source: impl std::fmt::Display,
+    ) -> Result<Self, io::Error> {
+        if cert_chain.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no client certificate found in {source}"),
+            ));
+        }
+        Ok(Self::new(cert_chain, key))
+    }
+
+    /// The certificate chain, starting with the end-entity certificate
+    pub fn cert_chain(&self) -> &[CertificateDer<'static>] {
+        &self.cert_chain
+    }
+
+    /// The private key
+    pub fn key(&self) -> &PrivateKeyDer<'static> {
+        &self.key
+    }
+
+    /// Loads the key with `provider`, checking that it matches the certificate
+    pub(crate) fn certified_key(
+        &self,
+        provider: &CryptoProvider,
+    ) -> Result<CertifiedKey, io::Error> {
+        CertifiedKey::from_der(self.cert_chain.clone(), self.key.clone_key(), provider)
+            .map_err(client_auth_error)
+    }
+
+    /// Finishes `builder` with this identity
+    pub(crate) fn configure(
+        self,
+        builder: ConfigBuilder<ClientConfig, WantsClientCert>,
+    ) -> Result<ClientConfig, io::Error> {
+        builder
+            .with_client_auth_cert(self.cert_chain, self.key)
+            .map_err(client_auth_error)
+    }
+}
+
+impl Clone for ClientIdentity {
+    fn clone(&self) -> Self {
+        Self {
+            cert_chain: self.cert_chain.clone(),
+            key: self.key.clone_key(),
+        }
+    }
+}
+
+fn client_auth_error(err: rustls::Error) -> io::Error {
+    let message = match err {
+        rustls::Error::InconsistentKeys(InconsistentKeys::KeyMismatch) => {
+            "client certificate does not match the private key".into()
+        }
+        rustls::Error::NoCertificatesPresented => "client certificate chain is empty".into(),
+        err => format!("invalid client identity: {err}"),
+    };
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn pem_error(what: &str, source: impl std::fmt::Display, err: pem::Error) -> io::Error {
+    let kind = match &err {
+        pem::Error::Io(err) => err.kind(),
+        _ => io::ErrorKind::InvalidData,
+    };
+    let err = match err {
+        pem::Error::NoItemsFound => "no PEM section found".into(),
+        err => err.to_string(),
+    };
+    io::Error::new(
+        kind,
+        format!("could not read client {what} from {source}: {err}"),
+    )
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use super::*;
+
+    const CERTS: &[u8] = include_bytes!("../../examples/sample.pem");
+    const KEY: &[u8] = include_bytes!("../../examples/sample.rsa");
+
+    #[test]
+    fn from_pem_files() {
+        let identity =
+            ClientIdentity::from_pem_files("examples/sample.pem", "examples/sample.rsa").unwrap();
+        assert_eq!(identity.cert_chain().len(), 3);
+        configure(identity).unwrap();
+    }
+
+    #[test]
+    fn missing_files() {
+        let err = ClientIdentity::from_pem_files("examples/missing.pem", "examples/sample.rsa")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .starts_with("could not read client certificate chain from examples/missing.pem"));
+
+        let err = ClientIdentity::from_pem_files("examples/sample.pem", "examples/sample.pem")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "could not read client private key from examples/sample.pem: no PEM section found"
+        );
+    }
+
+    #[test]
+    fn key_mismatch() {
+        // The key of the end-entity certificate, with the intermediate's chain
+        let mut identity = ClientIdentity::from_pem(CERTS, KEY).unwrap();
+        identity.cert_chain.remove(0);
+        let err = configure(identity).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "client certificate does not match the private key"
+        );
+    }
+
+    #[test]
+    fn empty_chain() {
+        let err = ClientIdentity::from_pem(b"", KEY).unwrap_err();
+        assert_eq!(err.to_string(), "no client certificate found in PEM buffer");
+
+        let identity = ClientIdentity::new(Vec::new(), PrivateKeyDer::from_pem_slice(KEY).unwrap());
+        let err = configure(identity).unwrap_err();
+        assert_eq!(err.to_string(), "client certificate chain is empty");
+    }
+
+    fn configure(identity: ClientIdentity) -> Result<ClientConfig, io::Error> {
+        identity.configure(
+            ClientConfig::builder().with_root_certificates(rustls::RootCertStore::empty()),
+        )
+    }
+}
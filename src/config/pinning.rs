@@ -0,0 +1,509 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme,
+};
+
+/// A [`ServerCertVerifier`] checking the public keys of certificate chains
+/// against per-host pins
+///
+/// This wraps another verifier, such as rustls' `WebPkiServerVerifier` or
+/// the platform verifier, which performs the regular certificate validation
+/// first. For hosts that have pins configured, at least one certificate of
+/// the chain must then have a public key matching one of the pins. Hosts
+/// without pins are only checked by the inner verifier.
+///
+/// By default, the chain is the one presented by the server: the end-entity
+/// certificate, and the paths from it through the intermediates the server
+/// sent, each certificate signing the previous one. Certificates the server
+/// sends that do not sign their way up from the end entity are ignored. The
+/// root the inner verifier trusts is only known when the server sends it,
+/// which most servers don't, so pin the end-entity or an intermediate key,
+/// or pass the roots of the inner verifier with [`with_roots()`](Self::with_roots).
+///
+/// Pins are SHA-256 hashes of the DER-encoded `SubjectPublicKeyInfo`, as
+/// computed by `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`.
+///
+/// Checking pins needs SHA-256 and signature verification, which are taken
+/// from the process-default [`CryptoProvider`](rustls::crypto::CryptoProvider),
+/// or the one enabled by crate features.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "rustls-platform-verifier")]
+/// # fn doc(primary: [u8; 32], backup: [u8; 32]) -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::Arc;
+///
+/// use hyper_rustls::{PinSet, PinningVerifier};
+/// use rustls::crypto::CryptoProvider;
+/// use rustls_platform_verifier::Verifier;
+///
+/// let provider = CryptoProvider::get_default().ok_or("no crypto provider")?;
+/// let inner = Verifier::new(provider.clone())?;
+/// let verifier = PinningVerifier::new(Arc::new(inner))
+///     .with_pins("api.example.com", PinSet::new([primary]).with_backup(backup));
+/// let config = rustls::ClientConfig::builder()
+///     .dangerous()
+///     .with_custom_certificate_verifier(Arc::new(verifier))
+///     .with_no_client_auth();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    roots: Option<Arc<RootCertStore>>,
+    pins: HashMap<String, PinSet>,
+    report_only: bool,
+}
+
+impl PinningVerifier {
+    /// Creates a verifier delegating to `inner`, without any pins yet
+    pub fn new(inner: Arc<dyn ServerCertVerifier>) -> Self {
+        Self {
+            inner,
+            roots: None,
+            pins: HashMap::new(),
+            report_only: false,
+        }
+    }
+
+    /// Matches pins against the paths from the end-entity certificate to one
+    /// of `roots`, instead of the chain presented by the server
+    ///
+    /// This covers the root keys even when the server does not send its
+    /// root. The roots should be those trusted by the inner verifier: chains
+    /// that cannot be rebuilt to one of them fail the pin check.
+    pub fn with_roots(mut self, roots: impl Into<Arc<RootCertStore>>) -> Self {
+        self.roots = Some(roots.into());
+        self
+    }
+
+    /// Sets the pins for `host`
+    ///
+    /// `host` is a DNS name (compared case-insensitively) or an IP address,
+    /// matched exactly against the server name of the connection. Setting
+    /// pins again for the same host replaces them.
+    pub fn with_pins(mut self, host: &str, pins: PinSet) -> Self {
+        self.pins
+            .insert(host.to_ascii_lowercase(), pins);
+        self
+    }
+
+    /// Only log mismatching chains instead of rejecting them
+    ///
+    /// This allows trying out pins on live traffic before enforcing them.
+    /// Mismatches are logged as warnings, with the hashes of the presented
+    /// certificates.
+    pub fn with_report_only(mut self, report_only: bool) -> Self {
+        self.report_only = report_only;
+        self
+    }
+
+    fn check_pins(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        now: UnixTime,
+    ) -> Result<(), rustls::Error> {
+        let host = server_name.to_str();
+        let Some(pins) = self
+            .pins
+            .get(&host.to_ascii_lowercase())
+        else {
+            return Ok(());
+        };
+
+        let Some(algorithms) = crate::spki::signature_algorithms() else {
+            return Err(rustls::Error::General(
+                "no crypto provider available to check pins".into(),
+            ));
+        };
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|_| CertificateError::BadEncoding)?;
+        let no_hash =
+            || rustls::Error::General("no SHA-256 implementation available to check pins".into());
+        let end_entity_hash =
+            crate::spki::sha256_der(&cert.subject_public_key_info()).ok_or_else(no_hash)?;
+
+        // Without roots, paths end at any of the presented certificates, so
+        // that only those signing their way up from the end entity count
+        let anchors = match &self.roots {
+            Some(roots) => Cow::Borrowed(&roots.roots[..]),
+            None => Cow::Owned(
+                intermediates
+                    .iter()
+                    .filter_map(|cert| webpki::anchor_from_trusted_cert(cert).ok())
+                    .collect(),
+            ),
+        };
+
+        // Other paths are tried as long as the callback rejects them, so the
+        // chain is accepted if any path matches the pins.
+        let matched = Cell::new(pins.check(&[end_entity_hash]));
+        let hash_missing = Cell::new(false);
+        let presented = RefCell::new(vec![end_entity_hash]);
+        let check = |path: &webpki::VerifiedPath<'_>| {
+            let Some(hashes) = path_hashes(path) else {
+                hash_missing.set(true);
+                return Err(webpki::Error::UnknownIssuer);
+            };
+            match pins.check(&hashes) {
+                Match::None => {
+                    presented.replace(hashes);
+                    Err(webpki::Error::UnknownIssuer)
+                }
+                found => {
+                    matched.set(found);
+                    Ok(())
+                }
+            }
+        };
+        if matched.get() != Match::Primary {
+            // The result is in `matched`, the error only says no path matched
+            let _ = cert.verify_for_usage(
+                algorithms.all,
+                &anchors,
+                intermediates,
+                now,
+                webpki::KeyUsage::server_auth(),
+                None,
+                Some(&check),
+            );
+        }
+
+        match matched.get() {
+            Match::Primary => return Ok(()),
+            Match::Backup => {
+                crate::log::warn!("certificate chain for {host} only matches a backup pin");
+                return Ok(());
+            }
+            Match::None if hash_missing.get() => return Err(no_hash()),
+            Match::None => {}
+        }
+
+        let presented = presented
+            .into_inner()
+            .iter()
+            .map(|hash| Hex(hash).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if self.report_only {
+            crate::log::warn!(
+                "certificate chain for {host} matches no pin (report only), verified public keys: [{presented}]"
+            );
+            return Ok(());
+        }
+
+        crate::log::warn!(
+            "certificate chain for {host} matches no pin, verified public keys: [{presented}]"
+        );
+        Err(CertificateError::ApplicationVerificationFailure.into())
+    }
+}
+
+/// The SPKI hashes of the certificates on a verified path, from the end
+/// entity to the trust anchor
+fn path_hashes(path: &webpki::VerifiedPath<'_>) -> Option<Vec<[u8; 32]>> {
+    let certs = [path.end_entity() as &webpki::Cert<'_>]
+        .into_iter()
+        .chain(path.intermediate_certificates());
+    let mut hashes = Vec::new();
+    for cert in certs {
+        hashes.push(crate::spki::sha256_der(&cert.subject_public_key_info())?);
+    }
+    hashes.push(crate::spki::sha256(&path.anchor().subject_public_key_info)?);
+    Some(hashes)
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        self.check_pins(end_entity, intermediates, server_name, now)?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        self.inner.requires_raw_public_keys()
+    }
+
+    fn root_hint_subjects(&self) -> Option<&[DistinguishedName]> {
+        self.inner.root_hint_subjects()
+    }
+}
+
+/// The public key pins of a host, see [`PinningVerifier`]
+///
+/// A chain is accepted if it matches any primary or backup pin. Backup pins
+/// are meant for keys that are not in use yet, so that certificates can be
+/// rotated without updating clients first; a chain that only matches a
+/// backup pin is logged.
+#[derive(Clone, Debug, Default)]
+pub struct PinSet {
+    primary: Vec<[u8; 32]>,
+    backup: Vec<[u8; 32]>,
+}
+
+impl PinSet {
+    /// Creates a set from the SHA-256 hashes of the keys currently in use
+    pub fn new(pins: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        Self {
+            primary: pins.into_iter().collect(),
+            backup: Vec::new(),
+        }
+    }
+
+    /// Adds a backup pin
+    pub fn with_backup(mut self, pin: [u8; 32]) -> Self {
+        self.backup.push(pin);
+        self
+    }
+
+    fn check(&self, hashes: &[[u8; 32]]) -> Match {
+        if hashes
+            .iter()
+            .any(|hash| self.primary.contains(hash))
+        {
+            Match::Primary
+        } else if hashes
+            .iter()
+            .any(|hash| self.backup.contains(hash))
+        {
+            Match::Backup
+        } else {
+            Match::None
+        }
+    }
+}
+
+/// How a chain matches a [`PinSet`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Match {
+    Primary,
+    Backup,
+    None,
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0
+            .iter()
+            .try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::RootCertStore;
+
+    use super::*;
+    use crate::test_util::sample_certs;
+
+    #[test]
+    fn matches_pins() {
+        let (end_entity, intermediate, root) = (pin(0), pin(1), pin(2));
+        let cases = [
+            (PinSet::new([intermediate]), true),
+            (PinSet::new([root]), true),
+            (PinSet::new([end_entity, [0; 32]]), true),
+            (PinSet::new([[0; 32]]), false),
+            (PinSet::new([[0; 32]]).with_backup(end_entity), true),
+            (PinSet::default(), false),
+        ];
+        for (pins, ok) in cases {
+            for verifier in [verifier(), rooted_verifier()] {
+                let verifier = verifier.with_pins("LocalHost", pins.clone());
+                assert_eq!(verify(&verifier, "localhost").is_ok(), ok);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_with_application_failure() {
+        let verifier = verifier().with_pins("localhost", PinSet::new([[0; 32]]));
+        assert_eq!(
+            verify(&verifier, "localhost").unwrap_err(),
+            rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+        );
+    }
+
+    #[test]
+    fn report_only() {
+        let verifier = verifier()
+            .with_pins("localhost", PinSet::new([[0; 32]]))
+            .with_report_only(true);
+        verify(&verifier, "localhost").unwrap();
+    }
+
+    #[test]
+    fn unpinned_host() {
+        let verifier = verifier().with_pins("example.com", PinSet::new([[0; 32]]));
+        verify(&verifier, "localhost").unwrap();
+    }
+
+    #[test]
+    fn inner_verifier_runs_first() {
+        let verifier = verifier().with_pins("example.com", PinSet::new([pin(0)]));
+        assert!(matches!(
+            verify(&verifier, "example.com"),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn ignores_certificates_outside_the_verified_path() {
+        // A valid chain from another CA, with the pinned intermediate appended
+        let mut chain = unrelated();
+        chain.truncate(1);
+        chain.push(sample_certs().swap_remove(1));
+
+        for verifier in [verifier(), rooted_verifier()] {
+            let pinned = verifier.with_pins("localhost", PinSet::new([pin(1)]));
+            assert_eq!(
+                verify_chain(&pinned, &chain, "localhost").unwrap_err(),
+                rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+            );
+        }
+
+        let pinned = rooted_verifier().with_pins("localhost", PinSet::new([unrelated_pin()]));
+        verify_chain(&pinned, &chain, "localhost").unwrap();
+    }
+
+    #[test]
+    fn root_pins_need_the_root() {
+        // The server does not send its root
+        let mut chain = sample_certs();
+        chain.pop();
+
+        let pinned = verifier().with_pins("localhost", PinSet::new([pin(2)]));
+        assert_eq!(
+            verify_chain(&pinned, &chain, "localhost").unwrap_err(),
+            rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+        );
+        let pinned = verifier().with_pins("localhost", PinSet::new([pin(1)]));
+        verify_chain(&pinned, &chain, "localhost").unwrap();
+
+        let pinned = rooted_verifier().with_pins("localhost", PinSet::new([pin(2)]));
+        verify_chain(&pinned, &chain, "localhost").unwrap();
+    }
+
+    fn verify(verifier: &PinningVerifier, name: &str) -> Result<ServerCertVerified, rustls::Error> {
+        verify_chain(verifier, &sample_certs(), name)
+    }
+
+    fn verify_chain(
+        verifier: &PinningVerifier,
+        chain: &[CertificateDer<'static>],
+        name: &str,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        verifier.verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &ServerName::try_from(name).unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+    }
+
+    /// A verifier trusting the roots of both the sample and the unrelated
+    /// chain, matching pins against the presented chain
+    fn verifier() -> PinningVerifier {
+        PinningVerifier::new(
+            WebPkiServerVerifier::builder(roots())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Like [`verifier()`], matching pins against the paths to its roots
+    fn rooted_verifier() -> PinningVerifier {
+        verifier().with_roots(roots())
+    }
+
+    fn roots() -> Arc<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(sample_certs().pop().unwrap())
+            .unwrap();
+        roots
+            .add(unrelated().pop().unwrap())
+            .unwrap();
+        Arc::new(roots)
+    }
+
+    /// The SPKI hash of the certificate at `index` in the sample chain
+    fn pin(index: usize) -> [u8; 32] {
+        spki_hash(&sample_certs().swap_remove(index))
+    }
+
+    /// The SPKI hash of the unrelated root
+    fn unrelated_pin() -> [u8; 32] {
+        spki_hash(&unrelated().pop().unwrap())
+    }
+
+    fn spki_hash(cert: &CertificateDer<'_>) -> [u8; 32] {
+        let anchor = webpki::anchor_from_trusted_cert(cert).unwrap();
+        crate::spki::sha256(&anchor.subject_public_key_info).unwrap()
+    }
+
+    fn unrelated() -> Vec<CertificateDer<'static>> {
+        chain(include_bytes!("../../examples/unrelated.pem"))
+    }
+
+    fn chain(pem: &[u8]) -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_slice_iter(pem)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use rustls::pki_types::TrustAnchor;
+use rustls::{DistinguishedName, RootCertStore};
+
+/// A builder combining root certificates from several sources
+///
+/// Roots can be taken from the platform, the webpki-roots bundle and PEM
+/// files, in any combination. Duplicates are removed, and specific
+/// certificate authorities can be excluded by the hash of their public key or
+/// by their subject. The resulting [`RootCertStore`] is meant to be passed to
+/// [`ConfigBuilder::with_root_certificates()`](rustls::ConfigBuilder::with_root_certificates).
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(all(feature = "rustls-native-certs", feature = "webpki-roots"))]
+/// # fn doc() -> std::io::Result<()> {
+/// use hyper_rustls::RootsBuilder;
+///
+/// let roots = RootsBuilder::new()
+///     .with_native_roots()?
+///     .with_webpki_roots()
+///     .with_pem_file("/etc/staging/ca.pem")?
+///     .build()?;
+/// let config = rustls::ClientConfig::builder()
+///     .with_root_certificates(roots)
+///     .with_no_client_auth();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RootsBuilder {
+    roots: Vec<TrustAnchor<'static>>,
+    excluded_spki: Vec<[u8; 32]>,
+    excluded_subjects: Vec<DistinguishedName>,
+}
+
+impl RootsBuilder {
+    /// Creates a builder without any roots
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the platform's trusted certs, as implemented by rustls-native-certs
+    ///
+    /// This fails under the same conditions as
+    /// [`ConfigBuilderExt::with_native_roots()`](crate::ConfigBuilderExt::with_native_roots).
+    #[cfg(feature = "rustls-native-certs")]
+    pub fn with_native_roots(self) -> Result<Self, io::Error> {
+        Ok(self.with_root_store(super::native_roots()?))
+    }
+
+    /// Adds the webpki roots, which are Mozilla's set of trusted roots as
+    /// packaged by webpki-roots
+    #[cfg(feature = "webpki-roots")]
+    pub fn with_webpki_roots(self) -> Self {
+        self.with_root_store(super::webpki_roots())
+    }
+
+    /// Adds the certificates in a PEM file
+    ///
+    /// See [`ConfigBuilderExt::with_pem_roots_file()`](crate::ConfigBuilderExt::with_pem_roots_file).
+    pub fn with_pem_file(self, path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Ok(self.with_root_store(super::pem_file_roots(path.as_ref())?))
+    }
+
+    /// Adds the certificates in all PEM files of a directory
+    ///
+    /// See [`ConfigBuilderExt::with_pem_roots_dir()`](crate::ConfigBuilderExt::with_pem_roots_dir).
+    pub fn with_pem_dir(self, path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Ok(self.with_root_store(super::pem_dir_roots(path.as_ref())?))
+    }
+
+    /// Adds the certificates in a PEM-encoded buffer
+    ///
+    /// See [`ConfigBuilderExt::with_pem_roots_bytes()`](crate::ConfigBuilderExt::with_pem_roots_bytes).
+    pub fn with_pem_bytes(self, pem: &[u8]) -> Result<Self, io::Error> {
+        Ok(self.with_root_store(super::pem_bytes_roots(pem)?))
+    }
+
+    /// Adds the roots of an existing store
+    pub fn with_root_store(mut self, store: RootCertStore) -> Self {
+        self.roots.extend(store.roots);
+        self
+    }
+
+    /// Excludes the certificate authority whose `SubjectPublicKeyInfo` has
+    /// the given SHA-256 hash
+    ///
+    /// This is the usual public key fingerprint, as computed by
+    /// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`.
+    pub fn exclude_spki_sha256(mut self, hash: [u8; 32]) -> Self {
+        self.excluded_spki.push(hash);
+        self
+    }
+
+    /// Excludes the certificate authorities with the given subject
+    ///
+    /// The subject is compared to the DER encoding of the `Name` in the
+    /// certificates, as returned by [`RootCertStore::subjects()`].
+    pub fn exclude_subject(mut self, subject: DistinguishedName) -> Self {
+        self.excluded_subjects.push(subject);
+        self
+    }
+
+    /// Builds the root store, removing duplicates and excluded roots
+    ///
+    /// Excluding roots by SPKI hash needs SHA-256, which is taken from the
+    /// process-default [`CryptoProvider`](rustls::crypto::CryptoProvider), or
+    /// the one enabled by crate features. This returns an error if no such
+    /// provider is available, or if no roots are left.
+    pub fn build(self) -> Result<RootCertStore, io::Error> {
+        let Self {
+            roots,
+            excluded_spki,
+            excluded_subjects,
+        } = self;
+
+        let mut seen = HashSet::new();
+        let mut store = RootCertStore::empty();
+        let mut excluded_count = 0;
+        for anchor in roots {
+            if seen.contains(&anchor) {
+                continue;
+            }
+
+            let subject = DistinguishedName::in_sequence(&anchor.subject);
+            let excluded = excluded_subjects
+                .iter()
+                .any(|excluded| excluded.as_ref() == subject.as_ref())
+                || (!excluded_spki.is_empty() && excluded_spki.contains(&spki_sha256(&anchor)?));
+            if excluded {
+                excluded_count += 1;
+            } else {
+                store.roots.push(anchor.clone());
+            }
+            seen.insert(anchor);
+        }
+
+        crate::log::debug!(
+            "RootsBuilder kept {} roots and excluded {excluded_count}",
+            store.len()
+        );
+        if store.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no root CA certificates found ({excluded_count} excluded)"),
+            ));
+        }
+
+        Ok(store)
+    }
+}
+
+fn spki_sha256(anchor: &TrustAnchor<'_>) -> Result<[u8; 32], io::Error> {
+    crate::spki::sha256(&anchor.subject_public_key_info).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "excluding roots by SPKI hash requires a crypto provider",
+        )
+    })
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../../examples/sample.pem");
+
+    #[test]
+    fn merges_and_deduplicates() {
+        let roots = RootsBuilder::new()
+            .with_pem_bytes(SAMPLE)
+            .unwrap()
+            .with_pem_file("examples/sample.pem")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(roots.len(), 3);
+    }
+
+    #[cfg(feature = "webpki-roots")]
+    #[test]
+    fn merges_webpki_roots() {
+        let roots = RootsBuilder::new()
+            .with_webpki_roots()
+            .with_pem_bytes(SAMPLE)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(roots.len(), webpki_roots::TLS_SERVER_ROOTS.len() + 3);
+    }
+
+    #[test]
+    fn excludes_by_spki_hash() {
+        // The sample CA, see `crate::spki::tests`
+        let roots = RootsBuilder::new()
+            .with_pem_bytes(SAMPLE)
+            .unwrap()
+            .exclude_spki_sha256(
+                *b"\xad\x5c\x8d\x1e\x5c\x6e\xd7\x02\xc7\x27\x0b\x07\x97\xae\x4a\x91\
+                   \xd9\xc4\xbd\x79\x67\xf5\xf3\x1b\xb7\xf6\xab\x09\xeb\x25\x57\x7f",
+            )
+            .build()
+            .unwrap();
+        assert_eq!(roots.len(), 2);
+        assert!(!roots
+            .roots
+            .iter()
+            .any(|anchor| anchor == &ca()));
+    }
+
+    #[test]
+    fn excludes_by_subject() {
+        let subject = DistinguishedName::in_sequence(&ca().subject);
+        let roots = RootsBuilder::new()
+            .with_pem_bytes(SAMPLE)
+            .unwrap()
+            .exclude_subject(subject.clone())
+            .build()
+            .unwrap();
+        assert_eq!(roots.len(), 2);
+        assert!(!roots
+            .subjects()
+            .iter()
+            .any(|name| name.as_ref() == subject.as_ref()));
+    }
+
+    #[test]
+    fn fails_without_roots() {
+        let err = RootsBuilder::new().build().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let sample = RootsBuilder::new()
+            .with_pem_bytes(SAMPLE)
+            .unwrap();
+        let subjects = sample
+            .clone()
+            .build()
+            .unwrap()
+            .subjects();
+        let err = subjects
+            .into_iter()
+            .fold(sample, RootsBuilder::exclude_subject)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no root CA certificates found (3 excluded)"
+        );
+    }
+
+    /// The trust anchor of the sample CA
+    fn ca() -> TrustAnchor<'static> {
+        let mut store = RootCertStore::empty();
+        store
+            .add(
+                CertificateDer::pem_slice_iter(SAMPLE)
+                    .last()
+                    .unwrap()
+                    .unwrap(),
+            )
+            .unwrap();
+        store.roots.pop().unwrap()
+    }
+}
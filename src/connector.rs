@@ -2,6 +2,7 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{fmt, io};
 
 use http::Uri;
@@ -12,19 +13,46 @@ use rustls::pki_types::ServerName;
 use tokio_rustls::TlsConnector;
 use tower_service::Service;
 
-use crate::stream::MaybeHttpsStream;
+use crate::error::BoxError;
+use crate::stream::{HttpsStream, MaybeHttpsStream};
+use crate::Error;
 
+pub(crate) mod alt_svc;
 pub(crate) mod builder;
+pub(crate) mod client_cert;
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub(crate) mod early_data;
+pub(crate) mod ech;
+pub(crate) mod handle;
+#[cfg(feature = "http3")]
+pub(crate) mod http3;
+pub(crate) mod prior_knowledge;
+pub(crate) mod proxy;
+pub(crate) mod resumption;
+pub(crate) mod router;
 
-type BoxError = Box<dyn std::error::Error + Send + Sync>;
+use client_cert::ClientCertResolver;
+use ech::Ech;
+use handle::ClientConfigHandle;
+use prior_knowledge::Http2PriorKnowledge;
+use proxy::{Proxy, Tunnel};
+use resumption::{ResumptionCounters, ResumptionStats};
 
 /// A Connector for the `https` scheme.
 #[derive(Clone)]
 pub struct HttpsConnector<T> {
     force_https: bool,
     http: T,
-    tls_config: Arc<rustls::ClientConfig>,
+    tls_config: ClientConfigHandle,
+    config_resolver: Option<Arc<dyn ResolveClientConfig + Sync + Send>>,
+    client_cert_resolver: Option<ClientCertResolver>,
+    ech: Option<Ech>,
     server_name_resolver: Arc<dyn ResolveServerName + Sync + Send>,
+    handshake_timeout: Option<Duration>,
+    proxy: Option<Arc<Proxy>>,
+    resumption: Arc<ResumptionCounters>,
+    early_data: bool,
+    http2_prior_knowledge: Option<Http2PriorKnowledge>,
 }
 
 impl<T> HttpsConnector<T> {
@@ -46,9 +74,17 @@ impl<T> HttpsConnector<T> {
     ) -> Self {
         Self {
             http,
-            tls_config: tls_config.into(),
+            tls_config: ClientConfigHandle::new(tls_config.into()),
+            config_resolver: None,
+            client_cert_resolver: None,
+            ech: None,
             force_https,
             server_name_resolver,
+            handshake_timeout: None,
+            proxy: None,
+            resumption: Arc::default(),
+            early_data: false,
+            http2_prior_knowledge: None,
         }
     }
 
@@ -58,6 +94,116 @@ impl<T> HttpsConnector<T> {
     pub fn enforce_https(&mut self) {
         self.force_https = true;
     }
+
+    /// Set a timeout for the TLS handshake.
+    ///
+    /// The timeout starts once the underlying connection has been established,
+    /// so it does not include the time spent connecting. If the handshake does not
+    /// complete in time, [`Error::HandshakeTimeout`] is returned. Passing `None`
+    /// (the default) waits indefinitely.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Set an HTTP proxy to tunnel connections through.
+    ///
+    /// See [`Proxy`] for details. Passing `None` (the default) connects
+    /// directly to every destination.
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.proxy = proxy.map(Arc::new);
+    }
+
+    /// Set a resolver picking the TLS configuration for each destination.
+    ///
+    /// Destinations for which the resolver returns `None` use the connector's
+    /// own configuration, see [`tls_config_handle()`](Self::tls_config_handle).
+    /// The ALPN protocols of resolved configurations are rewritten to match
+    /// the HTTP versions enabled on the connector. Passing `None` (the
+    /// default) uses the connector's configuration for every destination.
+    pub fn set_config_resolver(
+        &mut self,
+        resolver: Option<Arc<dyn ResolveClientConfig + Sync + Send>>,
+    ) {
+        self.config_resolver = resolver;
+    }
+
+    /// Set a resolver choosing the client certificate for each connection.
+    ///
+    /// This replaces the client certificate resolver of every configuration
+    /// used by the connector, including the ones picked by the
+    /// [`ResolveClientConfig`]. The resolver sees the server name produced by
+    /// the [`ResolveServerName`]. Passing `None` (the default) keeps the
+    /// configurations' own client authentication.
+    pub fn set_client_cert_resolver(&mut self, resolver: Option<ClientCertResolver>) {
+        self.client_cert_resolver = resolver;
+    }
+
+    /// Set the Encrypted Client Hello (ECH) settings.
+    ///
+    /// These apply to every configuration used by the connector, including
+    /// the ones picked by the [`ResolveClientConfig`]. See [`Ech`] for
+    /// details. Passing `None` (the default) connects without ECH.
+    pub fn set_ech(&mut self, ech: Option<Ech>) {
+        self.ech = ech;
+    }
+
+    /// Send requests as TLS 1.3 early data (0-RTT) when resuming a session.
+    ///
+    /// When the connector resumes a session with a server that allows early
+    /// data, the connection is returned before the handshake completes, and
+    /// the first request is sent along with the handshake, saving a round
+    /// trip. If the server rejects the early data, the request is sent again
+    /// once the handshake completes. See [`MaybeHttpsStream::early_data()`]
+    /// and [`EarlyDataStatus`](crate::EarlyDataStatus) to find out whether
+    /// it was accepted.
+    ///
+    /// Early data can be replayed by an attacker, and whatever is written
+    /// first on the connection is sent as early data, so only use this on
+    /// connectors for idempotent requests without side effects. The
+    /// connector only sees the destination of each connection and not the
+    /// requests sent over it; to pick the requests allowed in early data
+    /// individually, send them through an [`EarlyDataClient`](crate::EarlyDataClient)
+    /// and mark them with [`AllowEarlyData`](crate::AllowEarlyData).
+    ///
+    /// Early data is only sent when HTTP/2 is not offered, since hyper picks
+    /// the HTTP version from the ALPN protocol negotiated in the handshake.
+    /// The [handshake timeout](Self::set_handshake_timeout) does not apply to
+    /// connections returned before the handshake completes, and their
+    /// [`TlsInfo`](crate::TlsInfo) and [resumption stats](Self::resumption_stats)
+    /// only cover what is known when they are returned.
+    ///
+    /// This is disabled by default.
+    pub fn set_early_data(&mut self, enabled: bool) {
+        self.early_data = enabled;
+    }
+
+    /// Speak HTTP/2 with prior knowledge (h2c) to some plain `http://` destinations.
+    ///
+    /// Connections to the destinations selected by `prior_knowledge` are
+    /// returned as [`MaybeHttpsStream::H2c`], so that hyper speaks HTTP/2 over
+    /// them without negotiating it. This has no effect on `https://`
+    /// destinations, which use ALPN, nor on plain destinations reached through
+    /// a TLS-protected proxy. Passing `None` (the default) speaks HTTP/1 to
+    /// every plain destination.
+    pub fn set_http2_prior_knowledge(&mut self, prior_knowledge: Option<Http2PriorKnowledge>) {
+        self.http2_prior_knowledge = prior_knowledge;
+    }
+
+    /// Counts of the TLS handshakes that resumed a session or not.
+    ///
+    /// The counts cover the connections made by this connector and all its
+    /// clones. See [`ResumptionStats`].
+    pub fn resumption_stats(&self) -> ResumptionStats {
+        self.resumption.stats()
+    }
+
+    /// The handle to the TLS configuration used for new connections.
+    ///
+    /// The handle is shared with all clones of this connector, and can be
+    /// used to replace the configuration at runtime. See [`ClientConfigHandle`].
+    pub fn tls_config_handle(&self) -> &ClientConfigHandle {
+        &self.tls_config
+    }
 }
 
 impl<T> Service<Uri> for HttpsConnector<T>
@@ -77,52 +223,221 @@ where
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         match self.http.poll_ready(cx) {
             Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
-            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(Error::Connect(e.into()).into())),
             Poll::Pending => Poll::Pending,
         }
     }
 
     fn call(&mut self, dst: Uri) -> Self::Future {
+        self.call_via(dst.clone(), dst)
+    }
+}
+
+impl<T> HttpsConnector<T>
+where
+    T: Service<Uri>,
+    T::Response: Connection + rt::Read + rt::Write + Send + Unpin + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError>,
+{
+    /// Connects to `dst` through the endpoint of `target`
+    ///
+    /// `target` is `dst` itself, unless connecting to an alternative service
+    /// for it. Everything but the endpoint, such as the server name and the
+    /// proxy decision, is derived from `dst`.
+    pub(crate) fn call_via(&mut self, dst: Uri, target: Uri) -> <Self as Service<Uri>>::Future {
+        let proxy = self
+            .proxy
+            .clone()
+            .filter(|proxy| proxy.intercepts(&dst));
+        let handshake_timeout = self.handshake_timeout;
+
         // dst.scheme() would need to derive Eq to be matchable;
         // use an if cascade instead
         match dst.scheme() {
             Some(scheme) if scheme == &http::uri::Scheme::HTTP && !self.force_https => {
-                let future = self.http.call(dst);
+                let h2c = self
+                    .http2_prior_knowledge
+                    .as_ref()
+                    .is_some_and(|prior_knowledge| prior_knowledge.applies(&dst));
+                let plain = move |tcp| match h2c {
+                    true => MaybeHttpsStream::H2c(tcp),
+                    false => MaybeHttpsStream::Http(tcp),
+                };
+                let future = self.connect(&target, proxy.as_deref());
                 return Box::pin(async move {
-                    Ok(MaybeHttpsStream::Http(future.await.map_err(Into::into)?))
+                    let tcp = future
+                        .await
+                        .map_err(|e| Error::Connect(e.into()))?;
+                    let Some(proxy) = proxy else {
+                        return Ok(plain(tcp));
+                    };
+                    Ok(
+                        match proxy
+                            .open(tcp, &target, handshake_timeout)
+                            .await?
+                        {
+                            Tunnel::Plain(tcp) => plain(tcp),
+                            Tunnel::Tls(tls) => MaybeHttpsStream::HttpOverTls(tls),
+                        },
+                    )
                 });
             }
             Some(scheme) if scheme != &http::uri::Scheme::HTTPS => {
-                let message = format!("unsupported scheme {scheme}");
-                return Box::pin(async move { Err(io::Error::other(message).into()) });
+                let err = Error::UnsupportedScheme(scheme.clone());
+                return Box::pin(async move { Err(err.into()) });
             }
             Some(_) => {}
-            None => return Box::pin(async move { Err(io::Error::other("missing scheme").into()) }),
+            None => return Box::pin(async move { Err(Error::MissingScheme.into()) }),
         };
 
-        let cfg = self.tls_config.clone();
         let hostname = match self.server_name_resolver.resolve(&dst) {
             Ok(hostname) => hostname,
             Err(e) => {
-                return Box::pin(async move { Err(e) });
+                return Box::pin(async move { Err(Error::ServerName(e).into()) });
             }
         };
+        let cfg = select_tls_config(
+            &self.tls_config,
+            self.config_resolver.as_deref(),
+            self.client_cert_resolver.as_ref(),
+            &dst,
+            &hostname,
+        );
+        let cfg = match self
+            .ech
+            .as_ref()
+            .map(|ech| ech.configure(&dst, &cfg))
+        {
+            Some(Ok(Some(ech_cfg))) => Arc::new(ech_cfg),
+            Some(Err(e)) => return Box::pin(async move { Err(Error::Tls(e).into()) }),
+            Some(Ok(None)) | None => cfg,
+        };
+        // Early data is only sent when hyper can speak HTTP/1 without waiting
+        // for the handshake, since the negotiated ALPN protocol is not known
+        // before it completes
+        let early_data = self.early_data
+            && !cfg
+                .alpn_protocols
+                .iter()
+                .any(|protocol| protocol == b"h2");
+        let cfg = match early_data && !cfg.enable_early_data {
+            true => {
+                let mut cfg = Arc::unwrap_or_clone(cfg);
+                cfg.enable_early_data = true;
+                Arc::new(cfg)
+            }
+            false => cfg,
+        };
+        let sni = sent_server_name(&cfg, &hostname);
 
-        let connecting_future = self.http.call(dst);
+        let resumption = self.resumption.clone();
+        let connecting_future = self.connect(&target, proxy.as_deref());
         Box::pin(async move {
             let tcp = connecting_future
                 .await
-                .map_err(Into::into)?;
-            Ok(MaybeHttpsStream::Https(TokioIo::new(
-                TlsConnector::from(cfg)
-                    .connect(hostname, TokioIo::new(tcp))
-                    .await
-                    .map_err(io::Error::other)?,
-            )))
+                .map_err(|e| Error::Connect(e.into()))?;
+            let tunnel = match proxy {
+                Some(proxy) => {
+                    proxy
+                        .open(tcp, &target, handshake_timeout)
+                        .await?
+                }
+                None => Tunnel::Plain(tcp),
+            };
+
+            let connector = TlsConnector::from(cfg).early_data(early_data);
+            let stream = match tunnel {
+                Tunnel::Plain(tcp) => {
+                    let tls = handshake(
+                        connector.connect(hostname, TokioIo::new(tcp)),
+                        handshake_timeout,
+                    )
+                    .await?;
+                    resumption.record(tls.get_ref().1);
+                    HttpsStream::new(tls, sni)
+                }
+                Tunnel::Tls(tls) => {
+                    let tls = handshake(
+                        connector.connect(hostname, TokioIo::new(tls)),
+                        handshake_timeout,
+                    )
+                    .await?;
+                    resumption.record(tls.get_ref().1);
+                    HttpsStream::proxied(tls, sni)
+                }
+            };
+
+            // With early data, the handshake only starts with the first write
+            Ok(MaybeHttpsStream::Https(
+                match early_data && stream.get_ref().1.is_handshaking() {
+                    true => stream.with_early_data(),
+                    false => stream,
+                },
+            ))
         })
     }
 }
 
+/// Picks the TLS configuration for `dst`, choosing client certificates for `server_name`
+fn select_tls_config(
+    tls_config: &ClientConfigHandle,
+    config_resolver: Option<&(dyn ResolveClientConfig + Sync + Send)>,
+    client_cert_resolver: Option<&ClientCertResolver>,
+    dst: &Uri,
+    server_name: &ServerName<'static>,
+) -> Arc<rustls::ClientConfig> {
+    let cfg = match config_resolver.and_then(|resolver| resolver.resolve(dst)) {
+        Some(cfg) => tls_config.apply_alpn(cfg),
+        None => tls_config.load(),
+    };
+    match client_cert_resolver {
+        Some(resolver) => {
+            let mut cfg = Arc::unwrap_or_clone(cfg);
+            cfg.client_auth_cert_resolver = resolver.for_server(server_name);
+            Arc::new(cfg)
+        }
+        None => cfg,
+    }
+}
+
+/// Drives a TLS handshake to completion, subject to the optional timeout
+async fn handshake<S>(
+    handshake: impl Future<Output = io::Result<S>>,
+    timeout: Option<Duration>,
+) -> Result<S, Error> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handshake)
+            .await
+            .map_err(|_| Error::HandshakeTimeout)?,
+        None => handshake.await,
+    }
+    .map_err(Error::handshake)
+}
+
+/// Returns the name sent in the SNI extension when connecting to `name`
+///
+/// Only DNS names are sent, and only if SNI is enabled in the config.
+fn sent_server_name(
+    config: &rustls::ClientConfig,
+    name: &ServerName<'static>,
+) -> Option<ServerName<'static>> {
+    match name {
+        ServerName::DnsName(_) if config.enable_sni => Some(name.clone()),
+        _ => None,
+    }
+}
+
+impl<T: Service<Uri>> HttpsConnector<T> {
+    /// Connects to the proxy if one is used, otherwise directly to `dst`
+    fn connect(&mut self, dst: &Uri, proxy: Option<&Proxy>) -> T::Future {
+        match proxy {
+            Some(proxy) => self.http.call(proxy.connect_uri()),
+            None => self.http.call(dst.clone()),
+        }
+    }
+}
+
 impl<H, C> From<(H, C)> for HttpsConnector<H>
 where
     C: Into<Arc<rustls::ClientConfig>>,
@@ -131,8 +446,16 @@ where
         Self {
             force_https: false,
             http,
-            tls_config: cfg.into(),
+            tls_config: ClientConfigHandle::new(cfg.into()),
+            config_resolver: None,
+            client_cert_resolver: None,
+            ech: None,
             server_name_resolver: Arc::new(DefaultServerNameResolver::default()),
+            handshake_timeout: None,
+            proxy: None,
+            resumption: Arc::default(),
+            early_data: false,
+            http2_prior_knowledge: None,
         }
     }
 }
@@ -141,6 +464,13 @@ impl<T> fmt::Debug for HttpsConnector<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("HttpsConnector")
             .field("force_https", &self.force_https)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("proxy", &self.proxy)
+            .field("config_resolver", &self.config_resolver.is_some())
+            .field("client_cert_resolver", &self.client_cert_resolver)
+            .field("ech", &self.ech)
+            .field("early_data", &self.early_data)
+            .field("http2_prior_knowledge", &self.http2_prior_knowledge)
             .finish()
     }
 }
@@ -202,6 +532,25 @@ where
     }
 }
 
+/// A trait implemented by types that can pick the TLS configuration for a request.
+///
+/// See [`ClientConfigRouter`](crate::ClientConfigRouter) for an implementation
+/// routing by host and port.
+pub trait ResolveClientConfig {
+    /// Maps a [`Uri`] to the configuration used to connect to it, or `None`
+    /// to use the connector's own configuration.
+    fn resolve(&self, uri: &Uri) -> Option<Arc<rustls::ClientConfig>>;
+}
+
+impl<F> ResolveClientConfig for F
+where
+    F: Fn(&Uri) -> Option<Arc<rustls::ClientConfig>>,
+{
+    fn resolve(&self, uri: &Uri) -> Option<Arc<rustls::ClientConfig>> {
+        self(uri)
+    }
+}
+
 /// A trait implemented by types that can resolve a [`ServerName`] for a request.
 pub trait ResolveServerName {
     /// Maps a [`Uri`] into a [`ServerName`].
@@ -222,14 +571,26 @@ pub trait ResolveServerName {
 ))]
 mod tests {
     use std::future::poll_fn;
+    use std::net::{Ipv4Addr, SocketAddr};
 
     use http::Uri;
+    use hyper_util::client::legacy::connect::{HttpConnector, HttpInfo};
     use hyper_util::rt::TokioIo;
-    use tokio::net::TcpStream;
+    use rustls::server::WebPkiClientVerifier;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsAcceptor;
     use tower_service::Service;
 
     use super::*;
-    use crate::{ConfigBuilderExt, HttpsConnectorBuilder, MaybeHttpsStream};
+    use crate::test_util::{
+        connected_tls_info, sample_certs, sample_key, sample_roots, server_config, silent_server,
+        tls_server, tls_server_with, trusting_config,
+    };
+    use crate::{
+        AltSvcCache, AltSvcConnector, ClientCertResolver, ClientConfigRouter, ClientIdentity,
+        ConfigBuilderExt, EarlyData, EarlyDataStatus, HttpsConnectorBuilder, MaybeHttpsStream,
+    };
 
     #[tokio::test]
     async fn connects_https() {
@@ -262,6 +623,838 @@ mod tests {
         assert_eq!(message, "unsupported scheme http");
     }
 
+    #[tokio::test]
+    async fn unsupported_scheme() {
+        let err = local_connector()
+            .call(Uri::from_static("ftp://localhost"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnsupportedScheme(scheme)) if scheme.as_str() == "ftp"
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_scheme() {
+        let err = local_connector()
+            .call(Uri::from_static("localhost:443"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::MissingScheme)
+        ));
+    }
+
+    #[tokio::test]
+    async fn server_name_error() {
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(empty_roots_config())
+            .https_only()
+            .with_server_name_resolver(|_: &Uri| ServerName::try_from("not a name"))
+            .enable_http1()
+            .build();
+        let err = service
+            .call(Uri::from_static("https://localhost"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ServerName(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_error() {
+        // Bind and immediately drop a listener to get a port nobody listens on
+        let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = local_connector()
+            .call(
+                format!("https://{addr}")
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Connect(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn tls_error() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .await;
+        });
+
+        let err = local_connector()
+            .call(
+                format!("https://{addr}")
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Tls(_))));
+    }
+
+    #[tokio::test]
+    async fn io_error() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            drop(listener.accept().await.unwrap());
+        });
+
+        let err = local_connector()
+            .call(
+                format!("https://{addr}")
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let (addr, _server) = silent_server().await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(empty_roots_config())
+            .https_only()
+            .with_handshake_timeout(Duration::from_millis(50))
+            .enable_http1()
+            .build();
+
+        poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let err = service
+            .call(
+                format!("https://{addr}")
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::HandshakeTimeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_handshake_timeout() {
+        let (addr, _server) = silent_server().await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(empty_roots_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        service.set_handshake_timeout(Some(Duration::from_millis(50)));
+
+        poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let err = service
+            .call(
+                format!("https://{addr}")
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "TLS handshake timed out");
+    }
+
+    #[tokio::test]
+    async fn tls_info() {
+        let addr = tls_server(vec![b"h2".to_vec(), b"http/1.1".to_vec()]).await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+
+        let stream = service.call(uri.clone()).await.unwrap();
+        let info = connected_tls_info(&stream);
+        assert_eq!(
+            info.protocol_version(),
+            Some(rustls::ProtocolVersion::TLSv1_3)
+        );
+        assert!(info.cipher_suite().is_some());
+        assert_eq!(info.alpn_protocol(), None);
+        assert_eq!(
+            info.server_name(),
+            Some(&ServerName::try_from("localhost").unwrap())
+        );
+        assert_eq!(info.peer_certificates(), Some(&sample_certs()[..]));
+        assert!(!info.is_resumed());
+
+        // Read the server's greeting so the session ticket gets processed
+        let mut stream = TokioIo::new(stream);
+        let mut greeting = [0; 2];
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+
+        let stream = service.call(uri).await.unwrap();
+        assert!(connected_tls_info(&stream).is_resumed());
+    }
+
+    #[tokio::test]
+    async fn reload_tls_config() {
+        let addr = tls_server(Vec::new()).await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let established = service.call(uri.clone()).await.unwrap();
+
+        // Replacing the config through a clone affects the original connector
+        let handle = service
+            .clone()
+            .tls_config_handle()
+            .clone();
+        handle.store(empty_roots_config());
+        let err = service
+            .call(uri.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Tls(_))));
+
+        // Established connections are unaffected
+        let mut established = TokioIo::new(established);
+        let mut greeting = [0; 2];
+        established
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+        assert_eq!(&greeting, b"ok");
+
+        handle.store(trusting_config());
+        service.call(uri).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_auth() {
+        let addr = client_auth_server().await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let provider = rustls::ClientConfig::builder()
+            .crypto_provider()
+            .clone();
+
+        let identity =
+            ClientIdentity::from_pem_files("examples/sample.pem", "examples/sample.rsa").unwrap();
+        let mut service = HttpsConnectorBuilder::new()
+            .with_provider_roots_and_client_auth(provider, sample_roots(), identity)
+            .unwrap()
+            .https_only()
+            .enable_http1()
+            .build();
+        let mut stream = TokioIo::new(service.call(uri.clone()).await.unwrap());
+        let mut greeting = [0; 2];
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+        assert_eq!(&greeting, b"ok");
+
+        // The server rejects clients without a certificate after the handshake
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let mut stream = TokioIo::new(service.call(uri).await.unwrap());
+        assert!(stream
+            .read_exact(&mut greeting)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn client_cert_resolver() {
+        let addr = client_auth_server().await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let resolver = |host| {
+            ClientCertResolver::new(
+                rustls::ClientConfig::builder()
+                    .crypto_provider()
+                    .clone(),
+            )
+            .with_pem_files("examples/sample.pem", "examples/sample.rsa", &[host])
+            .unwrap()
+        };
+
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_client_cert_resolver(resolver("localhost"))
+            .enable_http1()
+            .build();
+        let mut stream = TokioIo::new(service.call(uri.clone()).await.unwrap());
+        let mut greeting = [0; 2];
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+        assert_eq!(&greeting, b"ok");
+
+        // Sessions made with the resolver can be resumed
+        let stream = service.call(uri.clone()).await.unwrap();
+        assert!(connected_tls_info(&stream).is_resumed());
+
+        // Identities restricted to other hosts are not sent
+        service.set_client_cert_resolver(Some(resolver("*.example.com")));
+        let mut stream = TokioIo::new(service.call(uri).await.unwrap());
+        assert!(stream
+            .read_exact(&mut greeting)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn resumption_stats() {
+        let addr = tls_server(Vec::new()).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let sessions = Arc::new(rustls::client::ClientSessionMemoryCache::new(64));
+        let config = trusting_config();
+        let build = || {
+            HttpsConnectorBuilder::new()
+                .with_tls_config(config.clone())
+                .https_only()
+                .with_session_store(sessions.clone())
+                .enable_http1()
+                .build()
+        };
+
+        let mut service = build();
+        connect_and_greet(&mut service, &uri).await;
+        connect_and_greet(&mut service.clone(), &uri).await;
+        assert_eq!(service.resumption_stats().hits(), 1);
+        assert_eq!(service.resumption_stats().misses(), 1);
+
+        // A rebuilt connector resumes sessions from the shared store
+        let mut rebuilt = build();
+        connect_and_greet(&mut rebuilt, &uri).await;
+        assert_eq!(rebuilt.resumption_stats().hits(), 1);
+        assert_eq!(rebuilt.resumption_stats().misses(), 0);
+
+        let mut uncached = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_session_cache_size(0)
+            .enable_http1()
+            .build();
+        connect_and_greet(&mut uncached, &uri).await;
+        connect_and_greet(&mut uncached, &uri).await;
+        assert_eq!(uncached.resumption_stats().hits(), 0);
+        assert_eq!(uncached.resumption_stats().misses(), 2);
+    }
+
+    #[tokio::test]
+    async fn early_data() {
+        let mut config = server_config();
+        config.max_early_data_size = 1024;
+        let addr = tls_server_with(TlsAcceptor::from(Arc::new(config))).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_early_data()
+            .enable_http1()
+            .build();
+
+        // Without a session to resume, the handshake completes before returning
+        let stream = service.call(uri.clone()).await.unwrap();
+        assert_eq!(stream.early_data(), None);
+        let mut stream = TokioIo::new(stream);
+        let mut greeting = [0; 2];
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+
+        let stream = service.call(uri).await.unwrap();
+        assert_eq!(stream.early_data(), Some(EarlyData::Pending));
+        let mut extensions = http::Extensions::new();
+        stream
+            .connected()
+            .get_extras(&mut extensions);
+        let status = extensions
+            .remove::<EarlyDataStatus>()
+            .expect("EarlyDataStatus missing from connection extras");
+        let mut stream = TokioIo::new(stream);
+        stream.write_all(b"x").await.unwrap();
+        stream.flush().await.unwrap();
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+        assert_eq!(stream.inner().early_data(), Some(EarlyData::Accepted));
+        assert_eq!(status.get(), EarlyData::Accepted);
+
+        // The session is resumed from the other server, which rejects it
+        let addr = tls_server(Vec::new()).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let mut stream = TokioIo::new(service.call(uri).await.unwrap());
+        stream.write_all(b"x").await.unwrap();
+        stream.flush().await.unwrap();
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+        assert_eq!(stream.inner().early_data(), Some(EarlyData::Rejected));
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn early_data_without_h2() {
+        let mut config = server_config();
+        config.max_early_data_size = 1024;
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let addr = tls_server_with(TlsAcceptor::from(Arc::new(config))).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_early_data()
+            .enable_http2()
+            .build();
+
+        connect_and_greet(&mut service, &uri).await;
+        let stream = service.call(uri).await.unwrap();
+        assert_eq!(stream.early_data(), None);
+        assert_eq!(service.resumption_stats().hits(), 1);
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    #[tokio::test]
+    async fn ech_grease() {
+        let addr = tls_server(Vec::new()).await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_ech(Ech::grease(sample_verifier()))
+            .enable_http1()
+            .build();
+
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let info = connected_tls_info(&service.call(uri).await.unwrap());
+        assert_eq!(info.ech_status(), rustls::client::EchStatus::Grease);
+        assert_eq!(
+            info.protocol_version(),
+            Some(rustls::ProtocolVersion::TLSv1_3)
+        );
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    #[tokio::test]
+    async fn ech_resolver() {
+        let (plain, offered) = (tls_server(Vec::new()).await, tls_server(Vec::new()).await);
+        let offered_port = offered.port();
+        let ech = Ech::with_resolver(
+            move |uri: &Uri| match uri.port_u16() == Some(offered_port) {
+                true => Some(ech_config_list("localhost")),
+                false => None,
+            },
+            sample_verifier(),
+        );
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_ech(ech)
+            .enable_http1()
+            .build();
+
+        // Destinations without a configuration are connected to without ECH
+        let uri = Uri::try_from(format!("https://localhost:{}", plain.port())).unwrap();
+        let info = connected_tls_info(&service.call(uri).await.unwrap());
+        assert_eq!(info.ech_status(), rustls::client::EchStatus::NotOffered);
+
+        // The test server does not support ECH, so it rejects the offer
+        // after authenticating as the public name
+        let uri = Uri::try_from(format!("https://localhost:{offered_port}")).unwrap();
+        let err = service.call(uri).await.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Tls(rustls::Error::PeerIncompatible(
+                    rustls::PeerIncompatible::ServerRejectedEncryptedClientHello(_)
+                )))
+            ),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    #[tokio::test]
+    async fn ech_invalid_config() {
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_ech(Ech::new(
+                rustls::pki_types::EchConfigListBytes::from(vec![0, 1, 2]),
+                sample_verifier(),
+            ))
+            .enable_http1()
+            .build();
+
+        let err = service
+            .call(Uri::from_static("https://localhost"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Tls(rustls::Error::InvalidEncryptedClientHello(_)))
+            ),
+            "{err}"
+        );
+    }
+
+    /// Encodes an ECH configuration list with a fresh P-256 key
+    #[cfg(feature = "aws-lc-rs")]
+    fn ech_config_list(public_name: &str) -> rustls::pki_types::EchConfigListBytes<'static> {
+        use rustls::crypto::aws_lc_rs::hpke::DH_KEM_P256_HKDF_SHA256_AES_128;
+        use rustls::crypto::hpke::Hpke;
+
+        let (public_key, _) = DH_KEM_P256_HKDF_SHA256_AES_128
+            .generate_key_pair()
+            .unwrap();
+        let mut contents = vec![1]; // config ID
+        contents.extend_from_slice(&0x0010u16.to_be_bytes()); // DHKEM(P-256, HKDF-SHA256)
+        contents.extend_from_slice(&(public_key.0.len() as u16).to_be_bytes());
+        contents.extend_from_slice(&public_key.0);
+        contents.extend_from_slice(&4u16.to_be_bytes());
+        contents.extend_from_slice(&0x0001u16.to_be_bytes()); // HKDF-SHA256
+        contents.extend_from_slice(&0x0001u16.to_be_bytes()); // AES-128-GCM
+        contents.push(0); // maximum name length
+        contents.push(public_name.len() as u8);
+        contents.extend_from_slice(public_name.as_bytes());
+        contents.extend_from_slice(&0u16.to_be_bytes()); // extensions
+
+        let mut config = 0xfe0du16.to_be_bytes().to_vec();
+        config.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        config.extend_from_slice(&contents);
+        let mut list = (config.len() as u16)
+            .to_be_bytes()
+            .to_vec();
+        list.extend_from_slice(&config);
+        rustls::pki_types::EchConfigListBytes::from(list)
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    fn sample_verifier() -> Arc<dyn rustls::client::danger::ServerCertVerifier> {
+        rustls::client::WebPkiServerVerifier::builder(Arc::new(sample_roots()))
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(feature = "key-log")]
+    #[tokio::test]
+    async fn key_log() {
+        #[derive(Debug, Default)]
+        struct Labels(std::sync::Mutex<Vec<String>>);
+
+        impl rustls::KeyLog for Labels {
+            fn log(&self, label: &str, _: &[u8], _: &[u8]) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(label.to_owned());
+            }
+        }
+
+        let addr = tls_server(Vec::new()).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let labels = Arc::new(Labels::default());
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_key_log(labels.clone())
+            .enable_http1()
+            .build();
+        connect_and_greet(&mut service, &uri).await;
+        assert!(labels
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|label| label == "CLIENT_TRAFFIC_SECRET_0"));
+    }
+
+    /// Reruns itself in a child process with `SSLKEYLOGFILE` set, since
+    /// changing the environment of the test harness would race with the
+    /// other tests
+    #[cfg(feature = "key-log")]
+    #[tokio::test]
+    async fn key_log_file() {
+        const CHILD: &str = "HYPER_RUSTLS_KEY_LOG_CHILD";
+        if std::env::var_os(CHILD).is_none() {
+            let dir = crate::test_util::TempDir::new("key-log");
+            let path = dir.path().join("keys");
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "connector::tests::key_log_file"])
+                .env(CHILD, "1")
+                .env("SSLKEYLOGFILE", &path)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            let keys = std::fs::read_to_string(&path).unwrap();
+            assert!(keys.contains("CLIENT_TRAFFIC_SECRET_0 "), "{keys}");
+            return;
+        }
+
+        let addr = tls_server(Vec::new()).await;
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_key_log_file()
+            .enable_http1()
+            .build();
+        connect_and_greet(&mut service, &uri).await;
+    }
+
+    #[tokio::test]
+    async fn custom_alpn_protocol() {
+        let addr = tls_server(vec![b"custom".to_vec()]).await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .with_alpn_protocol("custom")
+            .build();
+
+        let uri = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+        let stream = service.call(uri).await.unwrap();
+        assert!(!stream.connected().is_negotiated_h2());
+        let info = connected_tls_info(&stream);
+        assert_eq!(info.alpn_protocol(), Some(&b"custom"[..]));
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn http2_prior_knowledge() {
+        let (addr, _server) = silent_server().await;
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_or_http()
+            .enable_http2()
+            .with_http2_prior_knowledge(Http2PriorKnowledge::hosts(["LocalHost"]))
+            .build();
+
+        let uri = Uri::try_from(format!("http://localhost:{}", addr.port())).unwrap();
+        let stream = service.call(uri.clone()).await.unwrap();
+        assert!(matches!(stream, MaybeHttpsStream::H2c(_)));
+        assert!(stream.connected().is_negotiated_h2());
+
+        let other = Uri::try_from(format!("http://127.0.0.1:{}", addr.port())).unwrap();
+        let stream = service
+            .call(other.clone())
+            .await
+            .unwrap();
+        assert!(matches!(stream, MaybeHttpsStream::Http(_)));
+        assert!(!stream.connected().is_negotiated_h2());
+
+        service.set_http2_prior_knowledge(Some(Http2PriorKnowledge::all()));
+        let stream = service.call(other).await.unwrap();
+        assert!(stream.connected().is_negotiated_h2());
+
+        service.set_http2_prior_knowledge(None);
+        let stream = service.call(uri).await.unwrap();
+        assert!(matches!(stream, MaybeHttpsStream::Http(_)));
+    }
+
+    #[tokio::test]
+    async fn alt_svc_alternative() {
+        let origin = tls_server(Vec::new()).await;
+        let alternative = tls_server(Vec::new()).await;
+        let (mut service, cache) = alt_svc_connector();
+
+        // h2 is not offered by the connector, so the HTTP/1.1 alternative is used
+        let uri = Uri::try_from(format!("https://localhost:{}", origin.port())).unwrap();
+        cache.record(
+            &uri,
+            &alt_svc(&format!(
+                r#"h2=":1", http%2F1.1="127.0.0.1:{}""#,
+                alternative.port()
+            )),
+        );
+        let stream = service.call(uri.clone()).await.unwrap();
+        assert_eq!(remote_addr(&stream), alternative);
+        let info = connected_tls_info(&stream);
+        assert_eq!(
+            info.server_name(),
+            Some(&ServerName::try_from("localhost").unwrap())
+        );
+
+        cache.record(&uri, &alt_svc("clear"));
+        let stream = service.call(uri).await.unwrap();
+        assert_eq!(remote_addr(&stream).port(), origin.port());
+    }
+
+    #[tokio::test]
+    async fn alt_svc_fallback() {
+        let origin = tls_server(Vec::new()).await;
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (mut service, cache) = alt_svc_connector();
+
+        let uri = Uri::try_from(format!("https://localhost:{}", origin.port())).unwrap();
+        cache.record(
+            &uri,
+            &alt_svc(&format!(r#"http%2F1.1="127.0.0.1:{}""#, closed.port())),
+        );
+        let stream = service.call(uri.clone()).await.unwrap();
+        assert_eq!(remote_addr(&stream).port(), origin.port());
+        assert!(cache.alternatives(&uri).is_empty());
+    }
+
+    fn alt_svc_connector() -> (AltSvcConnector<HttpConnector>, AltSvcCache) {
+        let https = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let cache = AltSvcCache::new();
+        (AltSvcConnector::new(https, cache.clone()), cache)
+    }
+
+    fn alt_svc(value: &str) -> http::HeaderMap {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::ALT_SVC, value.parse().unwrap());
+        headers
+    }
+
+    fn remote_addr(stream: &MaybeHttpsStream<TokioIo<TcpStream>>) -> SocketAddr {
+        let mut extensions = http::Extensions::new();
+        stream
+            .connected()
+            .get_extras(&mut extensions);
+        extensions
+            .get::<HttpInfo>()
+            .expect("HttpInfo missing from connection extras")
+            .remote_addr()
+    }
+
+    /// Connects and reads the server's greeting, so the session ticket gets processed
+    async fn connect_and_greet(service: &mut HttpsConnector<HttpConnector>, uri: &Uri) {
+        let mut stream = TokioIo::new(service.call(uri.clone()).await.unwrap());
+        let mut greeting = [0; 2];
+        stream
+            .read_exact(&mut greeting)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn config_resolver() {
+        let addr = tls_server(vec![b"h2".to_vec(), b"http/1.1".to_vec()]).await;
+        let mut routed = trusting_config();
+        routed.alpn_protocols = vec![b"h2".to_vec()];
+        let mut service = HttpsConnectorBuilder::new()
+            .with_tls_config(empty_roots_config())
+            .https_only()
+            .with_config_resolver(ClientConfigRouter::new().with_route_and_port(
+                "localhost",
+                addr.port(),
+                routed,
+            ))
+            .enable_http1()
+            .build();
+
+        // The routed config is used, with the connector's ALPN protocols
+        let stream = service
+            .call(
+                format!("https://localhost:{}", addr.port())
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(connected_tls_info(&stream).alpn_protocol(), None);
+
+        // Unrouted destinations use the connector's config
+        let err = service
+            .call(
+                format!("https://127.0.0.1:{}", addr.port())
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Tls(_))));
+
+        service.set_config_resolver(None);
+        let err = service
+            .call(
+                format!("https://localhost:{}", addr.port())
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Tls(_))));
+    }
+
+    /// Serves TLS like [`tls_server()`], requiring clients to authenticate
+    /// with a certificate issued by the sample CA
+    async fn client_auth_server() -> SocketAddr {
+        let key = sample_key();
+        let verifier = WebPkiClientVerifier::builder(sample_roots().into())
+            .build()
+            .unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(sample_certs(), key)
+            .unwrap();
+        tls_server_with(TlsAcceptor::from(Arc::new(config))).await
+    }
+
+    fn local_connector() -> HttpsConnector<HttpConnector> {
+        HttpsConnectorBuilder::new()
+            .with_tls_config(empty_roots_config())
+            .https_only()
+            .enable_http1()
+            .build()
+    }
+
+    fn empty_roots_config() -> rustls::ClientConfig {
+        rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth()
+    }
+
     async fn connect(
         allow: Allow,
         scheme: Scheme,
@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use http::header::ALT_SVC;
+use http::uri::Scheme;
+use http::{HeaderMap, Uri};
+use hyper::rt;
+use hyper_util::client::legacy::connect::Connection;
+use tower_service::Service;
+
+use super::router::Host;
+use super::HttpsConnector;
+use crate::error::BoxError;
+use crate::stream::MaybeHttpsStream;
+
+/// How long an alternative is valid when it is advertised without `ma`
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A connector routing connections to the alternative services advertised
+/// by their origins
+///
+/// Origins advertise alternative services, which serve the same content
+/// from another endpoint or over another protocol, with the `Alt-Svc`
+/// response header ([RFC 7838](https://www.rfc-editor.org/rfc/rfc7838)).
+/// The connector does not see responses, so their headers have to be passed
+/// to [`AltSvcCache::record()`].
+///
+/// Connections to an `https` origin with a cached alternative are made to
+/// the alternative's endpoint, while the TLS handshake still verifies the
+/// origin's name. Only alternatives speaking a protocol offered by the
+/// wrapped [`HttpsConnector`] over TLS are used, such as `h2` and
+/// `http/1.1`; `h3` alternatives are left in the cache for
+/// `Http3Connector::call_alt_svc()` (with the `http3` feature), or other
+/// callers that can use them. If connecting to the alternative fails, it is removed
+/// from the cache and the connection is made to the origin instead.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(all(feature = "rustls-native-certs", feature = "http1"))]
+/// # async fn doc() -> Result<(), Box<dyn std::error::Error>> {
+/// use http_body_util::Empty;
+/// use hyper::body::Bytes;
+/// use hyper_rustls::{AltSvcCache, AltSvcConnector, HttpsConnectorBuilder};
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+///
+/// let https = HttpsConnectorBuilder::new()
+///     .with_native_roots()?
+///     .https_only()
+///     .enable_http1()
+///     .build();
+/// let cache = AltSvcCache::new();
+/// let client: Client<_, Empty<Bytes>> =
+///     Client::builder(TokioExecutor::new()).build(AltSvcConnector::new(https, cache.clone()));
+///
+/// let uri: http::Uri = "https://example.com/".parse()?;
+/// let response = client.get(uri.clone()).await?;
+/// cache.record(&uri, response.headers());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AltSvcConnector<T> {
+    inner: HttpsConnector<T>,
+    cache: AltSvcCache,
+}
+
+impl<T> AltSvcConnector<T> {
+    /// Routes the connections of `inner` to the alternatives found in `cache`
+    pub fn new(inner: HttpsConnector<T>, cache: AltSvcCache) -> Self {
+        Self { inner, cache }
+    }
+
+    /// The cache of alternative services used by this connector
+    pub fn cache(&self) -> &AltSvcCache {
+        &self.cache
+    }
+
+    /// Whether the wrapped connector can speak `protocol` over TLS
+    fn speaks(&self, protocol: &[u8]) -> bool {
+        let alpn_protocols = self
+            .inner
+            .tls_config_handle()
+            .alpn_protocols();
+        match alpn_protocols.is_empty() {
+            true => protocol == b"http/1.1",
+            false => alpn_protocols
+                .iter()
+                .any(|offered| offered == protocol),
+        }
+    }
+}
+
+impl<T> Service<Uri> for AltSvcConnector<T>
+where
+    T: Service<Uri> + Clone + Send + 'static,
+    T::Response: Connection + rt::Read + rt::Write + Send + Unpin + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError>,
+{
+    type Response = MaybeHttpsStream<T::Response>;
+    type Error = BoxError;
+
+    #[allow(clippy::type_complexity)]
+    type Future =
+        Pin<Box<dyn Future<Output = Result<MaybeHttpsStream<T::Response>, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let Some((alternative, target)) = self
+            .cache
+            .alternatives(&dst)
+            .into_iter()
+            .filter(|alternative| self.speaks(alternative.protocol()))
+            .find_map(|alternative| {
+                let target = alternative.target(&dst)?;
+                Some((alternative, target))
+            })
+        else {
+            return self.inner.call(dst);
+        };
+
+        let via_alternative = self.inner.call_via(dst.clone(), target);
+        let mut fallback = self.inner.clone();
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            match via_alternative.await {
+                Ok(stream) => Ok(stream),
+                Err(e) => {
+                    crate::log::debug!("alternative service for {dst} failed, falling back: {e}");
+                    cache.mark_broken(&dst, &alternative);
+                    poll_fn(|cx| fallback.poll_ready(cx)).await?;
+                    fallback.call(dst).await
+                }
+            }
+        })
+    }
+}
+
+impl<T> fmt::Debug for AltSvcConnector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AltSvcConnector")
+            .field("inner", &self.inner)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+/// The alternative services advertised by origins, shared between clones
+///
+/// Alternatives are recorded from the `Alt-Svc` headers of responses with
+/// [`record()`](Self::record), and kept in an [`AltSvcStore`] until they
+/// expire after their `ma` (max-age) parameter, 24 hours by default.
+/// Alternatives advertised with `persist=1` are kept when the network
+/// changes, see [`clear_transient()`](Self::clear_transient).
+#[derive(Clone)]
+pub struct AltSvcCache {
+    store: Arc<dyn AltSvcStore + Send + Sync>,
+}
+
+impl AltSvcCache {
+    /// Creates a cache kept in memory
+    pub fn new() -> Self {
+        Self::with_store(InMemoryAltSvcStore::default())
+    }
+
+    /// Creates a cache kept in `store`
+    pub fn with_store(store: impl AltSvcStore + Send + Sync + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Records the `Alt-Svc` headers of a response from `origin`
+    ///
+    /// The alternatives advertised in `headers` replace the ones cached for
+    /// `origin`, and `clear` removes them all. Responses without `Alt-Svc`
+    /// headers leave the cache untouched, and only `https` origins are
+    /// recorded.
+    pub fn record(&self, origin: &Uri, headers: &HeaderMap) {
+        let Some(key) = origin_key(origin) else {
+            return;
+        };
+        let values = headers
+            .get_all(ALT_SVC)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return;
+        }
+
+        let services = parse(&values.join(","), SystemTime::now());
+        self.store.set(&key, services);
+    }
+
+    /// The alternatives cached for `origin` that have not expired
+    pub fn alternatives(&self, origin: &Uri) -> Vec<AltService> {
+        let Some(key) = origin_key(origin) else {
+            return Vec::new();
+        };
+        let mut services = self.store.get(&key);
+        let now = SystemTime::now();
+        let cached = services.len();
+        services.retain(|service| service.expires > now);
+        if services.len() != cached {
+            self.store.set(&key, services.clone());
+        }
+        services
+    }
+
+    /// Removes `service` from the alternatives of `origin`, after failing to use it
+    pub fn mark_broken(&self, origin: &Uri, service: &AltService) {
+        let Some(key) = origin_key(origin) else {
+            return;
+        };
+        let mut services = self.store.get(&key);
+        services.retain(|cached| cached != service);
+        self.store.set(&key, services);
+    }
+
+    /// Removes the alternatives that were not advertised with `persist=1`
+    ///
+    /// This should be called when the network configuration changes, since
+    /// alternatives are only meant to be valid on the network they were
+    /// advertised on otherwise.
+    pub fn clear_transient(&self) {
+        self.store
+            .retain(&mut |_, service| service.persist);
+    }
+}
+
+impl Default for AltSvcCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AltSvcCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AltSvcCache")
+            .finish_non_exhaustive()
+    }
+}
+
+/// A storage for the alternative services of an [`AltSvcCache`]
+///
+/// Origins are identified by their lowercase host and port, like
+/// `example.com:443`. Implementations can keep alternatives across
+/// restarts, for example in a file; the cache ignores and removes the
+/// alternatives that have expired meanwhile.
+pub trait AltSvcStore {
+    /// Returns the alternatives stored for `origin`
+    fn get(&self, origin: &str) -> Vec<AltService>;
+
+    /// Replaces the alternatives stored for `origin`, removing them if `services` is empty
+    fn set(&self, origin: &str, services: Vec<AltService>);
+
+    /// Removes the alternatives for which `keep` returns `false`
+    fn retain(&self, keep: &mut dyn FnMut(&str, &AltService) -> bool);
+}
+
+/// An [`AltSvcStore`] keeping alternatives in memory
+///
+/// This is the store used by [`AltSvcCache::new()`].
+#[derive(Debug, Default)]
+pub struct InMemoryAltSvcStore {
+    origins: Mutex<HashMap<String, Vec<AltService>>>,
+}
+
+impl AltSvcStore for InMemoryAltSvcStore {
+    fn get(&self, origin: &str) -> Vec<AltService> {
+        self.origins
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(origin)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set(&self, origin: &str, services: Vec<AltService>) {
+        let mut origins = self
+            .origins
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match services.is_empty() {
+            true => origins.remove(origin),
+            false => origins.insert(origin.to_owned(), services),
+        };
+    }
+
+    fn retain(&self, keep: &mut dyn FnMut(&str, &AltService) -> bool) {
+        self.origins
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|origin, services| {
+                services.retain(|service| keep(origin, service));
+                !services.is_empty()
+            });
+    }
+}
+
+/// An alternative service advertised by an origin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AltService {
+    protocol: Vec<u8>,
+    host: Option<String>,
+    port: u16,
+    expires: SystemTime,
+    persist: bool,
+}
+
+impl AltService {
+    /// Creates an alternative speaking the ALPN `protocol` on `host` and
+    /// `port`, valid until `expires`
+    ///
+    /// A `host` of `None` stands for the origin's host.
+    pub fn new(
+        protocol: impl Into<Vec<u8>>,
+        host: Option<String>,
+        port: u16,
+        expires: SystemTime,
+    ) -> Self {
+        Self {
+            protocol: protocol.into(),
+            host,
+            port,
+            expires,
+            persist: false,
+        }
+    }
+
+    /// Whether the alternative is kept when the network changes
+    ///
+    /// This is disabled by default.
+    pub fn with_persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// The ALPN protocol spoken by the alternative, like `h2` or `h3`
+    pub fn protocol(&self) -> &[u8] {
+        &self.protocol
+    }
+
+    /// The host of the alternative, or `None` if it is the origin's host
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The port of the alternative
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// When the alternative expires
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+
+    /// Whether the alternative is kept when the network changes
+    pub fn persist(&self) -> bool {
+        self.persist
+    }
+
+    /// The URI of the alternative's endpoint, for connecting to `origin`
+    pub(crate) fn target(&self, origin: &Uri) -> Option<Uri> {
+        let host = match &self.host {
+            Some(host) => host.as_str(),
+            None => origin.host()?,
+        };
+        let authority = match host.contains(':') && !host.starts_with('[') {
+            true => format!("[{host}]:{}", self.port),
+            false => format!("{host}:{}", self.port),
+        };
+        Uri::builder()
+            .scheme(Scheme::HTTPS)
+            .authority(authority)
+            .path_and_query("/")
+            .build()
+            .ok()
+    }
+}
+
+/// The key of `origin` in the store, for `https` origins
+fn origin_key(origin: &Uri) -> Option<String> {
+    if origin.scheme() != Some(&Scheme::HTTPS) {
+        return None;
+    }
+    let host = Host::of(origin);
+    if host.is_empty() {
+        return None;
+    }
+    Some(format!("{host}:{}", origin.port_u16().unwrap_or(443)))
+}
+
+/// Parses an `Alt-Svc` header value, skipping invalid alternatives
+///
+/// `clear` yields no alternatives.
+fn parse(value: &str, now: SystemTime) -> Vec<AltService> {
+    if value.trim() == "clear" {
+        return Vec::new();
+    }
+
+    split_unquoted(value, b',')
+        .into_iter()
+        .filter_map(|entry| {
+            let mut parts = split_unquoted(entry, b';').into_iter();
+            let (protocol, authority) = parts.next()?.split_once('=')?;
+            let protocol = percent_decode(protocol.trim())?;
+            let authority = unquote(authority)?;
+            let (host, port) = authority.rsplit_once(':')?;
+            let port = port.parse().ok()?;
+            let host = host
+                .strip_prefix('[')
+                .and_then(|h| h.strip_suffix(']'))
+                .unwrap_or(host);
+
+            let mut max_age = DEFAULT_MAX_AGE;
+            let mut persist = false;
+            for parameter in parts {
+                let Some((name, value)) = parameter.split_once('=') else {
+                    continue;
+                };
+                let value = unquote(value)?;
+                match name.trim() {
+                    "ma" => max_age = Duration::from_secs(value.parse().ok()?),
+                    "persist" => persist = value == "1",
+                    _ => {}
+                }
+            }
+
+            Some(
+                AltService::new(
+                    protocol,
+                    (!host.is_empty()).then(|| host.to_owned()),
+                    port,
+                    now.checked_add(max_age)?,
+                )
+                .with_persist(persist),
+            )
+        })
+        .collect()
+}
+
+/// Splits `value` on `separator`, except within quoted strings
+fn split_unquoted(value: &str, separator: u8) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, byte) in value.bytes().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            _ if byte == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Returns the content of a token or quoted string
+fn unquote(value: &str) -> Option<String> {
+    let value = value.trim();
+    let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+    else {
+        return Some(value.to_owned());
+    };
+
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        unquoted.push(match c {
+            '\\' => chars.next()?,
+            c => c,
+        });
+    }
+    Some(unquoted)
+}
+
+/// Decodes a percent-encoded ALPN protocol ID, like `http%2F1.1`
+fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        decoded.push(match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            byte => byte,
+        });
+    }
+    match decoded.is_empty() {
+        true => None,
+        false => Some(decoded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use http::header::ALT_SVC;
+    use http::{HeaderMap, HeaderValue, Uri};
+
+    use super::{parse, AltService, AltSvcCache, DEFAULT_MAX_AGE};
+
+    #[test]
+    fn parse_alternatives() {
+        let now = SystemTime::now();
+        let services = parse(
+            r#"h3=":443"; ma=3600; persist=1, h2="alt.example.com:8443", http%2F1.1="[::1]:8080"; foo="a,b;c""#,
+            now,
+        );
+        assert_eq!(
+            services,
+            [
+                AltService::new("h3", None, 443, now + Duration::from_secs(3600))
+                    .with_persist(true),
+                AltService::new(
+                    "h2",
+                    Some("alt.example.com".into()),
+                    8443,
+                    now + DEFAULT_MAX_AGE
+                ),
+                AltService::new("http/1.1", Some("::1".into()), 8080, now + DEFAULT_MAX_AGE),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_skips_invalid() {
+        let now = SystemTime::now();
+        let services = parse(
+            r#"h2, h2="host", h2=":x", h2=":1"; ma=soon, h3=":443""#,
+            now,
+        );
+        assert_eq!(
+            services,
+            [AltService::new("h3", None, 443, now + DEFAULT_MAX_AGE)]
+        );
+        assert!(parse("clear", now).is_empty());
+    }
+
+    #[test]
+    fn record() {
+        let cache = AltSvcCache::new();
+        let origin = Uri::from_static("https://Example.com/path");
+        cache.record(&origin, &alt_svc(&[r#"h2=":8443""#, r#"h3=":443"; ma=0"#]));
+        let services = cache.alternatives(&Uri::from_static("https://example.com:443"));
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].protocol(), b"h2");
+        assert_eq!(services[0].port(), 8443);
+
+        // Other origins and schemes have their own alternatives
+        assert!(cache
+            .alternatives(&Uri::from_static("https://example.com:8443"))
+            .is_empty());
+        cache.record(
+            &Uri::from_static("http://example.com"),
+            &alt_svc(&["clear"]),
+        );
+        assert!(cache
+            .alternatives(&Uri::from_static("http://example.com"))
+            .is_empty());
+
+        // Responses without Alt-Svc keep the alternatives, new ones replace them
+        cache.record(&origin, &HeaderMap::new());
+        assert_eq!(cache.alternatives(&origin), services);
+        cache.record(&origin, &alt_svc(&["clear"]));
+        assert!(cache.alternatives(&origin).is_empty());
+    }
+
+    #[test]
+    fn clear_transient() {
+        let cache = AltSvcCache::new();
+        let origin = Uri::from_static("https://example.com");
+        cache.record(&origin, &alt_svc(&[r#"h2=":8443", h3=":443"; persist=1"#]));
+        assert_eq!(cache.alternatives(&origin).len(), 2);
+
+        cache.clear_transient();
+        let services = cache.alternatives(&origin);
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].protocol(), b"h3");
+
+        cache.mark_broken(&origin, &services[0]);
+        assert!(cache.alternatives(&origin).is_empty());
+    }
+
+    #[test]
+    fn target() {
+        let origin = Uri::from_static("https://example.com/path?query");
+        let service = AltService::new("h2", None, 8443, SystemTime::now());
+        assert_eq!(
+            service.target(&origin).unwrap(),
+            "https://example.com:8443/"
+        );
+        let service = AltService::new("h2", Some("::1".into()), 443, SystemTime::now());
+        assert_eq!(service.target(&origin).unwrap(), "https://[::1]:443/");
+    }
+
+    fn alt_svc(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ALT_SVC, HeaderValue::from_static(value));
+        }
+        headers
+    }
+}
@@ -1,22 +1,29 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use hyper_util::client::legacy::connect::HttpConnector;
-#[cfg(any(
-    feature = "rustls-native-certs",
-    feature = "rustls-platform-verifier",
-    feature = "webpki-roots"
-))]
+use rustls::client::{ClientSessionStore, Resumption};
 use rustls::crypto::CryptoProvider;
 use rustls::pki_types::ServerName;
-use rustls::ClientConfig;
+use rustls::{ClientConfig, RootCertStore};
 
-use super::{DefaultServerNameResolver, HttpsConnector, ResolveServerName};
+use super::client_cert::ClientCertResolver;
+use super::ech::Ech;
+use super::handle::ClientConfigHandle;
+#[cfg(feature = "http3")]
+use super::http3::Http3Connector;
+use super::prior_knowledge::Http2PriorKnowledge;
+use super::proxy::Proxy;
+use super::{DefaultServerNameResolver, HttpsConnector, ResolveClientConfig, ResolveServerName};
+use crate::config::env_roots;
+use crate::config::identity::ClientIdentity;
 #[cfg(any(
     feature = "rustls-native-certs",
-    feature = "webpki-roots",
-    feature = "rustls-platform-verifier"
+    feature = "rustls-platform-verifier",
+    feature = "webpki-roots"
 ))]
 use crate::config::ConfigBuilderExt;
+use crate::error::{BuildError, BuilderError};
 
 /// A builder for an [`HttpsConnector`]
 ///
@@ -52,30 +59,112 @@ impl ConnectorBuilder<WantsTlsConfig> {
     /// Passes a rustls [`ClientConfig`] to configure the TLS connection
     ///
     /// The [`alpn_protocols`](ClientConfig::alpn_protocols) field is
-    /// required to be empty (or the function will panic) and will be
-    /// rewritten to match the enabled schemes (see
-    /// [`enable_http1`](ConnectorBuilder::enable_http1),
+    /// required to be empty and will be rewritten to match the enabled
+    /// schemes (see [`enable_http1`](ConnectorBuilder::enable_http1),
     /// [`enable_http2`](ConnectorBuilder::enable_http2)) before the
-    /// connector is built.
-    pub fn with_tls_config(self, config: ClientConfig) -> ConnectorBuilder<WantsSchemes> {
-        assert!(
-            config.alpn_protocols.is_empty(),
-            "ALPN protocols should not be pre-defined"
-        );
-        ConnectorBuilder(WantsSchemes { tls_config: config })
+    /// connector is built. Protocols other than the HTTP versions can be
+    /// offered with [`with_alpn_protocol()`](ConnectorBuilder::with_alpn_protocol).
+    /// Preset protocols are reported as [`BuilderError::PresetAlpn`] by
+    /// `try_build()`, and make `build()` panic.
+    ///
+    /// This is the legacy form, kept for compatibility; prefer
+    /// [`try_with_tls_config()`](Self::try_with_tls_config), which reports
+    /// preset protocols right away.
+    pub fn with_tls_config(self, mut config: ClientConfig) -> ConnectorBuilder<WantsSchemes> {
+        let mut problems = Vec::new();
+        if !config.alpn_protocols.is_empty() {
+            problems.push(BuilderError::PresetAlpn(std::mem::take(
+                &mut config.alpn_protocols,
+            )));
+        }
+        ConnectorBuilder(WantsSchemes {
+            tls_config: Some(config),
+            problems,
+            roots: None,
+        })
+    }
+
+    /// Passes a [`ClientConfig`] trusting `roots`, which are checked when
+    /// the connector is built
+    fn with_tls_config_and_roots(
+        self,
+        config: ClientConfig,
+        roots: Arc<RootCertStore>,
+    ) -> ConnectorBuilder<WantsSchemes> {
+        let mut builder = self.with_tls_config(config);
+        builder.0.roots = Some(roots);
+        builder
+    }
+
+    /// Passes a rustls [`ClientConfig`] to configure the TLS connection,
+    /// checking it right away
+    ///
+    /// This is like [`with_tls_config()`](Self::with_tls_config), but
+    /// returns [`BuilderError::PresetAlpn`] if the configuration already has
+    /// ALPN protocols, which suits configurations loaded at runtime.
+    pub fn try_with_tls_config(
+        self,
+        config: ClientConfig,
+    ) -> Result<ConnectorBuilder<WantsSchemes>, BuilderError> {
+        if !config.alpn_protocols.is_empty() {
+            return Err(BuilderError::PresetAlpn(config.alpn_protocols));
+        }
+        Ok(self.with_tls_config(config))
+    }
+
+    /// Shorthand for using a custom [`CryptoProvider`], rustls' safe default
+    /// protocol versions and the given roots
+    ///
+    /// Problems with the provider or the roots are reported when the
+    /// connector is built, see [`try_build()`](ConnectorBuilder::try_build).
+    /// The roots can be combined from several sources with
+    /// [`RootsBuilder`](crate::RootsBuilder).
+    pub fn with_provider_and_roots(
+        self,
+        provider: impl Into<Arc<CryptoProvider>>,
+        roots: impl Into<Arc<RootCertStore>>,
+    ) -> ConnectorBuilder<WantsSchemes> {
+        let roots = roots.into();
+        let mut problems = Vec::new();
+        let tls_config = match ClientConfig::builder_with_provider(provider.into())
+            .with_safe_default_protocol_versions()
+        {
+            Ok(builder) => Some(
+                builder
+                    .with_root_certificates(roots.clone())
+                    .with_no_client_auth(),
+            ),
+            Err(err) => {
+                problems.push(BuilderError::IncompatibleProvider(err));
+                None
+            }
+        };
+        ConnectorBuilder(WantsSchemes {
+            tls_config,
+            problems,
+            roots: Some(roots),
+        })
     }
 
     /// Shorthand for using rustls' default crypto provider and other defaults, and
     /// the platform verifier.
     ///
-    /// See [`ConfigBuilderExt::with_platform_verifier()`].
+    /// See [`ConfigBuilderExt::with_platform_verifier()`]. If the verifier
+    /// cannot be initialized, this is reported when the connector is built,
+    /// see [`try_build()`](ConnectorBuilder::try_build).
     #[cfg(all(
         any(feature = "ring", feature = "aws-lc-rs"),
         feature = "rustls-platform-verifier"
     ))]
     pub fn with_platform_verifier(self) -> ConnectorBuilder<WantsSchemes> {
-        self.try_with_platform_verifier()
-            .expect("failure to initialize platform verifier")
+        match self.try_with_platform_verifier() {
+            Ok(builder) => builder,
+            Err(err) => ConnectorBuilder(WantsSchemes {
+                tls_config: None,
+                problems: vec![BuilderError::Verifier(err)],
+                roots: None,
+            }),
+        }
     }
 
     /// Shorthand for using rustls' default crypto provider and other defaults, and
@@ -146,6 +235,45 @@ impl ConnectorBuilder<WantsTlsConfig> {
         ))
     }
 
+    /// Shorthand for using rustls' default crypto provider and safe defaults, with
+    /// the roots named by `SSL_CERT_FILE` and `SSL_CERT_DIR`, or `fallback`.
+    ///
+    /// See [`ConfigBuilderExt::with_env_roots`](crate::ConfigBuilderExt::with_env_roots)
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    pub fn with_env_roots(
+        self,
+        fallback: impl FnOnce() -> std::io::Result<RootCertStore>,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let roots = Arc::new(env_roots(fallback)?);
+        Ok(self.with_tls_config_and_roots(
+            ClientConfig::builder()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth(),
+            roots,
+        ))
+    }
+
+    /// Shorthand for using a custom [`CryptoProvider`], with the roots named by
+    /// `SSL_CERT_FILE` and `SSL_CERT_DIR`, or `fallback`
+    ///
+    /// See [`ConfigBuilderExt::with_env_roots`](crate::ConfigBuilderExt::with_env_roots)
+    pub fn with_provider_and_env_roots(
+        self,
+        provider: impl Into<Arc<CryptoProvider>>,
+        fallback: impl FnOnce() -> std::io::Result<RootCertStore>,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let builder = ClientConfig::builder_with_provider(provider.into())
+            .with_safe_default_protocol_versions()
+            .map_err(std::io::Error::other)?;
+        let roots = Arc::new(env_roots(fallback)?);
+        Ok(self.with_tls_config_and_roots(
+            builder
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth(),
+            roots,
+        ))
+    }
+
     /// Shorthand for using rustls' default crypto provider and its
     /// safe defaults.
     ///
@@ -175,6 +303,95 @@ impl ConnectorBuilder<WantsTlsConfig> {
                 .with_no_client_auth(),
         ))
     }
+
+    /// Shorthand for using rustls' default crypto provider and other defaults,
+    /// the platform verifier, and a client certificate for mutual TLS.
+    ///
+    /// See [`ConfigBuilderExt::with_platform_verifier()`]. This fails if the
+    /// verifier cannot be initialized, or if the key does not match the
+    /// certificate.
+    #[cfg(all(
+        any(feature = "ring", feature = "aws-lc-rs"),
+        feature = "rustls-platform-verifier"
+    ))]
+    pub fn with_platform_verifier_and_client_auth(
+        self,
+        identity: ClientIdentity,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let builder = ClientConfig::builder()
+            .try_with_platform_verifier()
+            .map_err(std::io::Error::other)?;
+        Ok(self.with_tls_config(identity.configure(builder)?))
+    }
+
+    /// Shorthand for using rustls' default crypto provider and safe defaults,
+    /// native roots, and a client certificate for mutual TLS.
+    ///
+    /// See [`ConfigBuilderExt::with_native_roots`]. This fails if no native
+    /// roots are found, or if the key does not match the certificate.
+    #[cfg(all(
+        any(feature = "ring", feature = "aws-lc-rs"),
+        feature = "rustls-native-certs"
+    ))]
+    pub fn with_native_roots_and_client_auth(
+        self,
+        identity: ClientIdentity,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let builder = ClientConfig::builder().with_native_roots()?;
+        Ok(self.with_tls_config(identity.configure(builder)?))
+    }
+
+    /// Shorthand for using rustls' default crypto provider and safe defaults,
+    /// the roots named by `SSL_CERT_FILE` and `SSL_CERT_DIR` or `fallback`,
+    /// and a client certificate for mutual TLS.
+    ///
+    /// See [`ConfigBuilderExt::with_env_roots`](crate::ConfigBuilderExt::with_env_roots). This fails if no roots are
+    /// found, or if the key does not match the certificate.
+    #[cfg(any(feature = "ring", feature = "aws-lc-rs"))]
+    pub fn with_env_roots_and_client_auth(
+        self,
+        fallback: impl FnOnce() -> std::io::Result<RootCertStore>,
+        identity: ClientIdentity,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let roots = Arc::new(env_roots(fallback)?);
+        let builder = ClientConfig::builder().with_root_certificates(roots.clone());
+        Ok(self.with_tls_config_and_roots(identity.configure(builder)?, roots))
+    }
+
+    /// Shorthand for using rustls' default crypto provider and safe defaults,
+    /// Mozilla roots, and a client certificate for mutual TLS.
+    ///
+    /// See [`ConfigBuilderExt::with_webpki_roots`]. This fails if the key
+    /// does not match the certificate.
+    #[cfg(all(any(feature = "ring", feature = "aws-lc-rs"), feature = "webpki-roots"))]
+    pub fn with_webpki_roots_and_client_auth(
+        self,
+        identity: ClientIdentity,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let builder = ClientConfig::builder().with_webpki_roots();
+        Ok(self.with_tls_config(identity.configure(builder)?))
+    }
+
+    /// Shorthand for using a custom [`CryptoProvider`], the given roots, and
+    /// a client certificate for mutual TLS
+    ///
+    /// The roots can be combined from several sources with
+    /// [`RootsBuilder`](crate::RootsBuilder). This fails if the provider does
+    /// not support the safe default protocol versions, or if the key does not
+    /// match the certificate.
+    pub fn with_provider_roots_and_client_auth(
+        self,
+        provider: impl Into<Arc<CryptoProvider>>,
+        roots: impl Into<Arc<RootCertStore>>,
+        identity: ClientIdentity,
+    ) -> std::io::Result<ConnectorBuilder<WantsSchemes>> {
+        let roots = roots.into();
+        let builder = ClientConfig::builder_with_provider(provider.into())
+            .with_safe_default_protocol_versions()
+            .map_err(std::io::Error::other)?
+            .with_root_certificates(roots.clone());
+        Ok(self.with_tls_config_and_roots(identity.configure(builder)?, roots))
+    }
 }
 
 impl Default for ConnectorBuilder<WantsTlsConfig> {
@@ -186,7 +403,11 @@ impl Default for ConnectorBuilder<WantsTlsConfig> {
 /// State of a builder that needs schemes (https:// and http://) to be
 /// configured next
 pub struct WantsSchemes {
-    tls_config: ClientConfig,
+    /// `None` if a problem prevented building it
+    tls_config: Option<ClientConfig>,
+    problems: Vec<BuilderError>,
+    /// The roots trusted by `tls_config`, if the builder configured them
+    roots: Option<Arc<RootCertStore>>,
 }
 
 impl ConnectorBuilder<WantsSchemes> {
@@ -196,8 +417,17 @@ impl ConnectorBuilder<WantsSchemes> {
     pub fn https_only(self) -> ConnectorBuilder<WantsProtocols1> {
         ConnectorBuilder(WantsProtocols1 {
             tls_config: self.0.tls_config,
+            problems: self.0.problems,
+            roots: self.0.roots,
             https_only: true,
+            config_resolver: None,
+            client_cert_resolver: None,
+            ech: None,
+            early_data: false,
+            http2_prior_knowledge: None,
             server_name_resolver: None,
+            handshake_timeout: None,
+            proxy: None,
         })
     }
 
@@ -208,8 +438,17 @@ impl ConnectorBuilder<WantsSchemes> {
     pub fn https_or_http(self) -> ConnectorBuilder<WantsProtocols1> {
         ConnectorBuilder(WantsProtocols1 {
             tls_config: self.0.tls_config,
+            problems: self.0.problems,
+            roots: self.0.roots,
             https_only: false,
+            config_resolver: None,
+            client_cert_resolver: None,
+            ech: None,
+            early_data: false,
+            http2_prior_knowledge: None,
             server_name_resolver: None,
+            handshake_timeout: None,
+            proxy: None,
         })
     }
 }
@@ -219,28 +458,105 @@ impl ConnectorBuilder<WantsSchemes> {
 ///
 /// No protocol has been enabled at this point.
 pub struct WantsProtocols1 {
-    tls_config: ClientConfig,
+    tls_config: Option<ClientConfig>,
+    problems: Vec<BuilderError>,
+    roots: Option<Arc<RootCertStore>>,
     https_only: bool,
+    config_resolver: Option<Arc<dyn ResolveClientConfig + Sync + Send>>,
+    client_cert_resolver: Option<ClientCertResolver>,
+    ech: Option<Ech>,
+    early_data: bool,
+    http2_prior_knowledge: Option<Http2PriorKnowledge>,
     server_name_resolver: Option<Arc<dyn ResolveServerName + Sync + Send>>,
+    handshake_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
 }
 
 impl WantsProtocols1 {
-    fn wrap_connector<H>(self, conn: H) -> HttpsConnector<H> {
-        HttpsConnector {
+    /// The problems found while configuring the builder, and with the final roots
+    fn take_problems(&mut self) -> Vec<BuilderError> {
+        let mut problems = Vec::new();
+        if self
+            .roots
+            .as_ref()
+            .is_some_and(|roots| roots.is_empty())
+        {
+            problems.push(BuilderError::MissingRoots);
+        }
+        problems.append(&mut self.problems);
+        problems
+    }
+
+    /// Checks the configuration for the enabled HTTP versions, reporting all problems
+    fn try_wrap_connector<H>(
+        mut self,
+        conn: H,
+        http1: bool,
+        http2: bool,
+    ) -> Result<HttpsConnector<H>, BuildError> {
+        let mut problems = self.take_problems();
+        if let Some(tls_config) = &self.tls_config {
+            let h2 = tls_config
+                .alpn_protocols
+                .iter()
+                .any(|protocol| protocol == b"h2");
+            match (http1, http2, h2) {
+                (false, true, false) => problems.push(BuilderError::MissingHttp2Alpn),
+                (_, false, true) => problems.push(BuilderError::UnexpectedHttp2Alpn),
+                _ => {}
+            }
+        }
+        let tls_config = match self.tls_config {
+            Some(tls_config) if problems.is_empty() => tls_config,
+            _ => return Err(BuildError(problems)),
+        };
+
+        Ok(HttpsConnector {
             force_https: self.https_only,
             http: conn,
-            tls_config: Arc::new(self.tls_config),
+            tls_config: ClientConfigHandle::new(Arc::new(tls_config)),
+            config_resolver: self.config_resolver,
+            client_cert_resolver: self.client_cert_resolver,
+            ech: self.ech,
             server_name_resolver: self
                 .server_name_resolver
                 .unwrap_or_else(|| Arc::new(DefaultServerNameResolver::default())),
+            handshake_timeout: self.handshake_timeout,
+            proxy: self.proxy.map(Arc::new),
+            resumption: Arc::default(),
+            early_data: self.early_data,
+            http2_prior_knowledge: self.http2_prior_knowledge,
+        })
+    }
+
+    /// Applies `update` to the TLS configuration, unless a problem prevented building it
+    fn update_tls_config(&mut self, update: impl FnOnce(&mut ClientConfig)) {
+        if let Some(tls_config) = &mut self.tls_config {
+            update(tls_config);
         }
     }
 
-    fn build(self) -> HttpsConnector<HttpConnector> {
+    /// Offers `protocol` after the protocols offered so far
+    fn add_alpn_protocol(&mut self, protocol: Vec<u8>) {
+        self.update_tls_config(|tls_config| {
+            if !tls_config
+                .alpn_protocols
+                .contains(&protocol)
+            {
+                tls_config.alpn_protocols.push(protocol);
+            }
+        });
+    }
+
+    fn try_build(
+        self,
+        http1: bool,
+        http2: bool,
+    ) -> Result<HttpsConnector<HttpConnector>, BuildError> {
         let mut http = HttpConnector::new();
         // HttpConnector won't enforce scheme, but HttpsConnector will
         http.enforce_http(false);
-        self.wrap_connector(http)
+        self.try_wrap_connector(http, http1, http2)
     }
 }
 
@@ -258,7 +574,8 @@ impl ConnectorBuilder<WantsProtocols1> {
     /// This needs to be called explicitly, no protocol is enabled by default
     #[cfg(feature = "http2")]
     pub fn enable_http2(mut self) -> ConnectorBuilder<WantsProtocols3> {
-        self.0.tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        self.0
+            .update_tls_config(|tls_config| tls_config.alpn_protocols = vec![b"h2".to_vec()]);
         ConnectorBuilder(WantsProtocols3 {
             inner: self.0,
             enable_http1: false,
@@ -276,13 +593,153 @@ impl ConnectorBuilder<WantsProtocols1> {
         #[cfg(not(feature = "http1"))]
         let alpn_protocols = vec![b"h2".to_vec()];
 
-        self.0.tls_config.alpn_protocols = alpn_protocols;
+        self.0
+            .update_tls_config(|tls_config| tls_config.alpn_protocols = alpn_protocols);
         ConnectorBuilder(WantsProtocols3 {
             inner: self.0,
             enable_http1: cfg!(feature = "http1"),
         })
     }
 
+    /// Enable HTTP3, building an [`Http3Connector`] instead of an [`HttpsConnector`]
+    ///
+    /// HTTP/3 runs over QUIC rather than TCP, so it needs a connector of its
+    /// own, which offers `h3` via ALPN and only connects to `https` URLs.
+    /// The TLS configuration, the resolvers and the handshake timeout set up
+    /// so far are kept. A proxy, early data and ECH are not supported, and
+    /// are reported by [`try_build()`](ConnectorBuilder<WantsHttp3>::try_build).
+    #[cfg(feature = "http3")]
+    pub fn enable_http3(mut self) -> ConnectorBuilder<WantsHttp3> {
+        self.0
+            .update_tls_config(|tls_config| tls_config.alpn_protocols = vec![b"h3".to_vec()]);
+        ConnectorBuilder(WantsHttp3 { inner: self.0 })
+    }
+
+    /// Set a timeout for the TLS handshake
+    ///
+    /// By default, hyper-rustls waits indefinitely for the TLS handshake to
+    /// complete once the underlying connection is established. If this
+    /// method is called, connecting fails with
+    /// [`Error::HandshakeTimeout`](crate::Error::HandshakeTimeout) when the
+    /// handshake takes longer than `timeout`.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.0.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Tunnel connections through an HTTP proxy
+    ///
+    /// The connector will send a `CONNECT` request to the proxy for every
+    /// destination not excluded by the proxy's [`NoProxy`](crate::NoProxy)
+    /// list, and run the TLS handshake with the destination over the
+    /// resulting tunnel. Plain HTTP connections are tunnelled as well.
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.0.proxy = Some(proxy);
+        self
+    }
+
+    /// Keep up to `size` sessions in memory for resumption
+    ///
+    /// rustls keeps up to 256 sessions by default. A size of zero
+    /// disables resumption. This replaces the resumption settings of the TLS
+    /// configuration, see [`Resumption`].
+    pub fn with_session_cache_size(mut self, size: usize) -> Self {
+        self.0.update_tls_config(|tls_config| {
+            tls_config.resumption = match size {
+                0 => Resumption::disabled(),
+                size => Resumption::in_memory_sessions(size),
+            }
+        });
+        self
+    }
+
+    /// Store sessions for resumption in `store`
+    ///
+    /// Passing the same store to several connectors lets them resume each
+    /// other's sessions, so that rebuilding a connector does not lose them.
+    /// rustls only resumes sessions established with the same certificate
+    /// verifier and client certificate resolver, so the connectors must be
+    /// built from clones of the same [`ClientConfig`].
+    /// A custom [`ClientSessionStore`] can also persist sessions, for example
+    /// to disk between process restarts. This replaces the resumption
+    /// settings of the TLS configuration, see [`Resumption`].
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "webpki-roots", feature = "http1", feature = "aws-lc-rs"))]
+    /// # {
+    /// # let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    /// use std::sync::Arc;
+    ///
+    /// use hyper_rustls::{ConfigBuilderExt, HttpsConnectorBuilder};
+    /// use rustls::client::ClientSessionMemoryCache;
+    ///
+    /// let config = rustls::ClientConfig::builder()
+    ///     .with_webpki_roots()
+    ///     .with_no_client_auth();
+    /// let sessions = Arc::new(ClientSessionMemoryCache::new(1024));
+    /// let build = || {
+    ///     HttpsConnectorBuilder::new()
+    ///         .with_tls_config(config.clone())
+    ///         .https_only()
+    ///         .with_session_store(sessions.clone())
+    ///         .enable_http1()
+    ///         .build()
+    /// };
+    /// let (https, rebuilt) = (build(), build());
+    /// # }
+    /// ```
+    pub fn with_session_store(mut self, store: Arc<dyn ClientSessionStore>) -> Self {
+        self.0
+            .update_tls_config(|tls_config| tls_config.resumption = Resumption::store(store));
+        self
+    }
+
+    /// Log the TLS secrets of every connection to `key_log`
+    ///
+    /// This allows decrypting the traffic, for example in Wireshark, when
+    /// debugging. Anyone with access to the logged secrets can decrypt the
+    /// connections, so this must never be enabled in production; a warning
+    /// is logged whenever it is. Use [`rustls::KeyLogFile`] to write the
+    /// secrets to a file in the `SSLKEYLOGFILE` format, see also
+    /// [`with_key_log_file()`](Self::with_key_log_file).
+    ///
+    /// Configurations picked by a [`ResolveClientConfig`] keep their own
+    /// key log.
+    #[cfg(feature = "key-log")]
+    pub fn with_key_log(mut self, key_log: Arc<dyn rustls::KeyLog>) -> Self {
+        crate::log::warn!(
+            "TLS key logging is enabled with {key_log:?}: the secrets of all connections are \
+             exposed, this must not be used in production"
+        );
+        self.0
+            .update_tls_config(|tls_config| tls_config.key_log = key_log);
+        self
+    }
+
+    /// Log the TLS secrets of every connection to the file named by the
+    /// `SSLKEYLOGFILE` environment variable, if it is set
+    ///
+    /// See [`with_key_log()`](Self::with_key_log). Nothing is logged, and
+    /// no warning is issued, if the variable is not set.
+    #[cfg(feature = "key-log")]
+    pub fn with_key_log_file(self) -> Self {
+        match std::env::var_os("SSLKEYLOGFILE") {
+            Some(_) => self.with_key_log(Arc::new(rustls::KeyLogFile::new())),
+            None => self,
+        }
+    }
+
+    /// Send requests as TLS 1.3 early data (0-RTT) when resuming a session
+    ///
+    /// Early data can be replayed by an attacker, so this should only be
+    /// enabled for idempotent requests, or requests picked with an
+    /// [`EarlyDataClient`](crate::EarlyDataClient). See
+    /// [`HttpsConnector::set_early_data()`] for details.
+    pub fn with_early_data(mut self) -> Self {
+        self.0.early_data = true;
+        self
+    }
+
     /// Override server name for the TLS stack
     ///
     /// By default, for each connection hyper-rustls will extract host portion
@@ -299,6 +756,45 @@ impl ConnectorBuilder<WantsProtocols1> {
         self
     }
 
+    /// Pick the TLS configuration for each destination
+    ///
+    /// By default, hyper-rustls uses the configuration passed to the builder
+    /// for every connection. If this method is called, the resolver can
+    /// select another configuration, with its own roots and client
+    /// certificates, based on the destination URL. Its ALPN protocols are
+    /// rewritten to match the enabled HTTP versions.
+    ///
+    /// See [`ClientConfigRouter`](crate::ClientConfigRouter) for routing by
+    /// host and port.
+    pub fn with_config_resolver(
+        mut self,
+        resolver: impl ResolveClientConfig + 'static + Sync + Send,
+    ) -> Self {
+        self.0.config_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Choose the client certificate for each connection
+    ///
+    /// By default, hyper-rustls uses the client authentication of the TLS
+    /// configuration. If this method is called, the resolver picks among its
+    /// identities for every connection, based on the server name and the
+    /// server's certificate request. See [`ClientCertResolver`].
+    pub fn with_client_cert_resolver(mut self, resolver: ClientCertResolver) -> Self {
+        self.0.client_cert_resolver = Some(resolver);
+        self
+    }
+
+    /// Offer Encrypted Client Hello (ECH) to hide the server name
+    ///
+    /// By default, hyper-rustls sends the server name in the clear. If this
+    /// method is called, connections offer ECH or GREASE ECH as set up in
+    /// `ech`. See [`Ech`] for details.
+    pub fn with_ech(mut self, ech: Ech) -> Self {
+        self.0.ech = Some(ech);
+        self
+    }
+
     /// Override server name for the TLS stack
     ///
     /// By default, for each connection hyper-rustls will extract host portion
@@ -343,25 +839,97 @@ impl ConnectorBuilder<WantsProtocols2> {
     /// This needs to be called explicitly, no protocol is enabled by default
     #[cfg(feature = "http2")]
     pub fn enable_http2(mut self) -> ConnectorBuilder<WantsProtocols3> {
-        self.0.inner.tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        self.0
+            .inner
+            .update_tls_config(|tls_config| {
+                tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            });
         ConnectorBuilder(WantsProtocols3 {
             inner: self.0.inner,
             enable_http1: true,
         })
     }
 
+    /// Offer `protocol` via ALPN, after the protocols offered so far
+    ///
+    /// This allows negotiating protocols other than the HTTP versions, such
+    /// as custom tokens, on the same connector. The negotiated protocol is
+    /// reported by [`TlsInfo::alpn_protocol()`](crate::TlsInfo::alpn_protocol).
+    /// Since no ALPN protocol is offered for HTTP/1 alone, `http/1.1` is
+    /// offered first. See [`with_alpn_protocols()`](Self::with_alpn_protocols)
+    /// to choose the order of all protocols.
+    pub fn with_alpn_protocol(mut self, protocol: impl Into<Vec<u8>>) -> Self {
+        self.0
+            .inner
+            .update_tls_config(|tls_config| {
+                if tls_config.alpn_protocols.is_empty() {
+                    tls_config
+                        .alpn_protocols
+                        .push(b"http/1.1".to_vec());
+                }
+            });
+        self.0
+            .inner
+            .add_alpn_protocol(protocol.into());
+        self
+    }
+
+    /// Offer exactly `protocols` via ALPN, in this order
+    ///
+    /// This replaces the protocols derived from the enabled HTTP versions,
+    /// so they should be listed as well where they belong. `h2` must not be
+    /// listed, since HTTP/2 is not enabled.
+    pub fn with_alpn_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<Vec<u8>>>,
+    ) -> Self {
+        self.0
+            .inner
+            .update_tls_config(|tls_config| tls_config.alpn_protocols.clear());
+        for protocol in protocols {
+            self.0
+                .inner
+                .add_alpn_protocol(protocol.into());
+        }
+        self
+    }
+
     /// This builds an [`HttpsConnector`] built on hyper's default [`HttpConnector`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration has problems, see [`try_build()`](Self::try_build).
     pub fn build(self) -> HttpsConnector<HttpConnector> {
-        self.0.inner.build()
+        self.try_build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// This builds an [`HttpsConnector`] built on hyper's default [`HttpConnector`],
+    /// or reports all the problems found in the configuration
+    pub fn try_build(self) -> Result<HttpsConnector<HttpConnector>, BuildError> {
+        self.0.inner.try_build(true, false)
     }
 
     /// This wraps an arbitrary low-level connector into an [`HttpsConnector`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration has problems, see [`try_build()`](Self::try_build).
     pub fn wrap_connector<H>(self, conn: H) -> HttpsConnector<H> {
+        self.try_wrap_connector(conn)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// This wraps an arbitrary low-level connector into an [`HttpsConnector`],
+    /// or reports all the problems found in the configuration
+    pub fn try_wrap_connector<H>(self, conn: H) -> Result<HttpsConnector<H>, BuildError> {
         // HTTP1-only, alpn_protocols stays empty
         // HttpConnector doesn't have a way to say http1-only;
         // its connection pool may still support HTTP2
         // though it won't be used
-        self.0.inner.wrap_connector(conn)
+        self.0
+            .inner
+            .try_wrap_connector(conn, true, false)
     }
 }
 
@@ -373,24 +941,158 @@ impl ConnectorBuilder<WantsProtocols2> {
 #[cfg(feature = "http2")]
 pub struct WantsProtocols3 {
     inner: WantsProtocols1,
-    // ALPN is built piecemeal without the need to read back this field
-    #[allow(dead_code)]
     enable_http1: bool,
 }
 
 #[cfg(feature = "http2")]
 impl ConnectorBuilder<WantsProtocols3> {
+    /// Speak HTTP/2 with prior knowledge (h2c) to some plain `http://` destinations
+    ///
+    /// By default, hyper-rustls only reports HTTP/2 for connections that
+    /// negotiated it with ALPN, so plain connections use HTTP/1. If this
+    /// method is called, hyper speaks HTTP/2 right away to the plain
+    /// destinations selected by `prior_knowledge`, while `https://`
+    /// destinations keep using ALPN. This has no effect on
+    /// [`https_only()`](ConnectorBuilder<WantsSchemes>::https_only) connectors.
+    /// See [`Http2PriorKnowledge`] for details.
+    pub fn with_http2_prior_knowledge(mut self, prior_knowledge: Http2PriorKnowledge) -> Self {
+        self.0.inner.http2_prior_knowledge = Some(prior_knowledge);
+        self
+    }
+
+    /// Offer `protocol` via ALPN, after the protocols offered so far
+    ///
+    /// This allows negotiating protocols other than the HTTP versions, such
+    /// as custom tokens, on the same connector. The negotiated protocol is
+    /// reported by [`TlsInfo::alpn_protocol()`](crate::TlsInfo::alpn_protocol).
+    /// See [`with_alpn_protocols()`](Self::with_alpn_protocols) to choose
+    /// the order of all protocols.
+    pub fn with_alpn_protocol(mut self, protocol: impl Into<Vec<u8>>) -> Self {
+        self.0
+            .inner
+            .add_alpn_protocol(protocol.into());
+        self
+    }
+
+    /// Offer exactly `protocols` via ALPN, in this order
+    ///
+    /// This replaces the protocols derived from the enabled HTTP versions,
+    /// so they should be listed as well where they belong: `h2`, and
+    /// `http/1.1` if HTTP/1 is enabled.
+    pub fn with_alpn_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<Vec<u8>>>,
+    ) -> Self {
+        self.0
+            .inner
+            .update_tls_config(|tls_config| tls_config.alpn_protocols.clear());
+        for protocol in protocols {
+            self.0
+                .inner
+                .add_alpn_protocol(protocol.into());
+        }
+        self
+    }
+
     /// This builds an [`HttpsConnector`] built on hyper's default [`HttpConnector`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration has problems, see [`try_build()`](Self::try_build).
     pub fn build(self) -> HttpsConnector<HttpConnector> {
-        self.0.inner.build()
+        self.try_build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// This builds an [`HttpsConnector`] built on hyper's default [`HttpConnector`],
+    /// or reports all the problems found in the configuration
+    pub fn try_build(self) -> Result<HttpsConnector<HttpConnector>, BuildError> {
+        let http1 = self.0.enable_http1;
+        self.0.inner.try_build(http1, true)
     }
 
     /// This wraps an arbitrary low-level connector into an [`HttpsConnector`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration has problems, see [`try_build()`](Self::try_build).
     pub fn wrap_connector<H>(self, conn: H) -> HttpsConnector<H> {
+        self.try_wrap_connector(conn)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// This wraps an arbitrary low-level connector into an [`HttpsConnector`],
+    /// or reports all the problems found in the configuration
+    pub fn try_wrap_connector<H>(self, conn: H) -> Result<HttpsConnector<H>, BuildError> {
         // If HTTP1 is disabled, we can set http2_only
         // on the Client (a higher-level object that uses the connector)
         // client.http2_only(!self.0.enable_http1);
-        self.0.inner.wrap_connector(conn)
+        let http1 = self.0.enable_http1;
+        self.0
+            .inner
+            .try_wrap_connector(conn, http1, true)
+    }
+}
+
+/// State of a builder with HTTP3 enabled
+///
+/// At this point an [`Http3Connector`] can be built, see
+/// [`build`](ConnectorBuilder<WantsHttp3>::build).
+#[cfg(feature = "http3")]
+pub struct WantsHttp3 {
+    inner: WantsProtocols1,
+}
+
+#[cfg(feature = "http3")]
+impl ConnectorBuilder<WantsHttp3> {
+    /// This builds an [`Http3Connector`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration has problems, see [`try_build()`](Self::try_build).
+    pub fn build(self) -> Http3Connector {
+        self.try_build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// This builds an [`Http3Connector`], or reports all the problems found
+    /// in the configuration
+    pub fn try_build(self) -> Result<Http3Connector, BuildError> {
+        let mut inner = self.0.inner;
+        let mut problems = inner.take_problems();
+        if inner.proxy.is_some() {
+            problems.push(BuilderError::UnsupportedWithHttp3("a proxy"));
+        }
+        if inner.early_data {
+            problems.push(BuilderError::UnsupportedWithHttp3("early data"));
+        }
+        if inner.ech.is_some() {
+            problems.push(BuilderError::UnsupportedWithHttp3("ECH"));
+        }
+        if let Some(tls_config) = &inner.tls_config {
+            if !tls_config
+                .crypto_provider()
+                .cipher_suites
+                .iter()
+                .any(|suite| suite.suite() == rustls::CipherSuite::TLS13_AES_128_GCM_SHA256)
+            {
+                problems.push(BuilderError::MissingQuicCipherSuite);
+            }
+        }
+        let tls_config = match inner.tls_config {
+            Some(tls_config) if problems.is_empty() => tls_config,
+            _ => return Err(BuildError(problems)),
+        };
+
+        Ok(Http3Connector::new(
+            ClientConfigHandle::new(Arc::new(tls_config)),
+            inner.config_resolver,
+            inner.client_cert_resolver,
+            inner
+                .server_name_resolver
+                .unwrap_or_else(|| Arc::new(DefaultServerNameResolver::default())),
+            inner.handshake_timeout,
+        ))
     }
 }
 
@@ -425,6 +1127,196 @@ mod tests {
             .build();
     }
 
+    #[test]
+    fn test_try_with_tls_config() {
+        ensure_global_state();
+        let mut config_with_alpn = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        config_with_alpn.alpn_protocols = vec![b"fancyprotocol".to_vec()];
+        let result = super::ConnectorBuilder::new().try_with_tls_config(config_with_alpn);
+        assert!(matches!(
+            result,
+            Err(super::BuilderError::PresetAlpn(protocols)) if protocols == [b"fancyprotocol"]
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "http1")]
+    fn test_preset_alpn_is_reported() {
+        ensure_global_state();
+        let mut config_with_alpn = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        config_with_alpn.alpn_protocols = vec![b"fancyprotocol".to_vec()];
+        let err = super::ConnectorBuilder::new()
+            .with_tls_config(config_with_alpn)
+            .https_only()
+            .enable_http1()
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(
+            err.problems(),
+            [super::BuilderError::PresetAlpn(protocols)] if protocols == &[b"fancyprotocol"]
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "http1", any(feature = "ring", feature = "aws-lc-rs")))]
+    fn test_try_build_collects_problems() {
+        use rustls::crypto::CryptoProvider;
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::CertificateDer;
+
+        ensure_global_state();
+        let provider = CryptoProvider::get_default().unwrap();
+        let unusable = CryptoProvider {
+            cipher_suites: Vec::new(),
+            ..CryptoProvider::clone(provider)
+        };
+        let err = super::ConnectorBuilder::new()
+            .with_provider_and_roots(unusable, rustls::RootCertStore::empty())
+            .https_only()
+            .enable_http1()
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(
+            err.problems(),
+            [
+                super::BuilderError::MissingRoots,
+                super::BuilderError::IncompatibleProvider(_)
+            ]
+        ));
+        assert!(err.to_string().starts_with(
+            "invalid connector configuration: no root certificates; incompatible crypto provider: "
+        ));
+
+        let mut roots = rustls::RootCertStore::empty();
+        let ca = CertificateDer::pem_slice_iter(include_bytes!("../../examples/sample.pem"))
+            .last()
+            .unwrap()
+            .unwrap();
+        roots.add(ca).unwrap();
+        super::ConnectorBuilder::new()
+            .with_provider_and_roots(provider.clone(), roots)
+            .https_only()
+            .enable_http1()
+            .try_build()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "http1", any(feature = "ring", feature = "aws-lc-rs")))]
+    fn test_try_build_reports_missing_env_roots() {
+        ensure_global_state();
+        if std::env::var_os("SSL_CERT_FILE").is_some() || std::env::var_os("SSL_CERT_DIR").is_some()
+        {
+            return;
+        }
+        let err = super::ConnectorBuilder::new()
+            .with_env_roots(|| Ok(rustls::RootCertStore::empty()))
+            .unwrap()
+            .https_or_http()
+            .enable_http1()
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(
+            err.problems(),
+            [super::BuilderError::MissingRoots]
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn test_try_build_alpn() {
+        ensure_global_state();
+        let tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let err = super::ConnectorBuilder::new()
+            .with_tls_config(tls_config.clone())
+            .https_only()
+            .enable_http2()
+            .with_alpn_protocols(["custom"])
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(
+            err.problems(),
+            [super::BuilderError::MissingHttp2Alpn]
+        ));
+
+        let err = super::ConnectorBuilder::new()
+            .with_tls_config(tls_config.clone())
+            .https_only()
+            .enable_http1()
+            .with_alpn_protocols(["h2", "http/1.1"])
+            .try_build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid connector configuration: h2 is offered via ALPN, but HTTP/2 is not enabled"
+        );
+
+        // Without h2, HTTP/1 is still available
+        super::ConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_only()
+            .enable_http1()
+            .enable_http2()
+            .with_alpn_protocols(["http/1.1"])
+            .try_build()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "http1", feature = "http2"))]
+    fn test_custom_alpn() {
+        ensure_global_state();
+        let tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let connector = super::ConnectorBuilder::new()
+            .with_tls_config(tls_config.clone())
+            .https_only()
+            .enable_http1()
+            .with_alpn_protocol("custom")
+            .build();
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"http/1.1".to_vec(), b"custom".to_vec()]
+        );
+        let connector = super::ConnectorBuilder::new()
+            .with_tls_config(tls_config.clone())
+            .https_only()
+            .enable_all_versions()
+            .with_alpn_protocol("grpc-exp")
+            .with_alpn_protocol("h2")
+            .build();
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"h2".to_vec(), b"http/1.1".to_vec(), b"grpc-exp".to_vec()]
+        );
+        let connector = super::ConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_only()
+            .enable_all_versions()
+            .with_alpn_protocols(["grpc-exp", "h2", "http/1.1"])
+            .build();
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"grpc-exp".to_vec(), b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
     #[test]
     #[cfg(all(feature = "http1", feature = "http2"))]
     fn test_alpn() {
@@ -440,6 +1332,7 @@ mod tests {
             .build();
         assert!(connector
             .tls_config
+            .load()
             .alpn_protocols
             .is_empty());
         let connector = super::ConnectorBuilder::new()
@@ -447,7 +1340,13 @@ mod tests {
             .https_only()
             .enable_http2()
             .build();
-        assert_eq!(&connector.tls_config.alpn_protocols, &[b"h2".to_vec()]);
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"h2".to_vec()]
+        );
         let connector = super::ConnectorBuilder::new()
             .with_tls_config(tls_config.clone())
             .https_only()
@@ -455,7 +1354,10 @@ mod tests {
             .enable_http2()
             .build();
         assert_eq!(
-            &connector.tls_config.alpn_protocols,
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
             &[b"h2".to_vec(), b"http/1.1".to_vec()]
         );
         let connector = super::ConnectorBuilder::new()
@@ -464,7 +1366,10 @@ mod tests {
             .enable_all_versions()
             .build();
         assert_eq!(
-            &connector.tls_config.alpn_protocols,
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
             &[b"h2".to_vec(), b"http/1.1".to_vec()]
         );
     }
@@ -482,13 +1387,25 @@ mod tests {
             .https_only()
             .enable_http2()
             .build();
-        assert_eq!(&connector.tls_config.alpn_protocols, &[b"h2".to_vec()]);
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"h2".to_vec()]
+        );
         let connector = super::ConnectorBuilder::new()
             .with_tls_config(tls_config)
             .https_only()
             .enable_all_versions()
             .build();
-        assert_eq!(&connector.tls_config.alpn_protocols, &[b"h2".to_vec()]);
+        assert_eq!(
+            &connector
+                .tls_config
+                .load()
+                .alpn_protocols,
+            &[b"h2".to_vec()]
+        );
     }
 
     fn ensure_global_state() {
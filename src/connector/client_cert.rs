@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::{fmt, io};
+
+use rustls::client::ResolvesClientCert;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::ServerName;
+use rustls::sign::CertifiedKey;
+use rustls::{DistinguishedName, SignatureScheme};
+
+use super::router::Host;
+use crate::config::identity::ClientIdentity;
+
+/// A [`ResolvesClientCert`] choosing among several client identities
+///
+/// When a server asks for a client certificate, the first identity that
+/// suits the request is sent:
+///
+/// - it must be usable with one of the signature schemes the server supports,
+/// - if the server lists the certificate authorities it accepts, one of them
+///   must have issued a certificate of the identity's chain (or be part of it),
+/// - identities restricted to some hosts are only sent to those hosts, and
+///   take precedence over unrestricted identities. The most specific host
+///   pattern wins, as in [`ClientConfigRouter`](crate::ClientConfigRouter).
+///
+/// Hosts are matched against the server name produced by the connector's
+/// [`ResolveServerName`](crate::ResolveServerName). rustls does not give the
+/// server name to certificate resolvers, so host restrictions only apply when
+/// the resolver is set on the connector, with
+/// [`ConnectorBuilder::with_client_cert_resolver()`](crate::HttpsConnectorBuilder::with_client_cert_resolver);
+/// when used directly in a [`ClientConfig`](rustls::ClientConfig), only
+/// unrestricted identities are considered.
+///
+/// Identities loaded from files can be read again with
+/// [`reload()`](Self::reload), for example after certificates were renewed.
+/// Clones share their identities, so reloading affects every connector using
+/// the resolver.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "aws-lc-rs")]
+/// # fn doc() -> std::io::Result<()> {
+/// use hyper_rustls::ClientCertResolver;
+///
+/// let resolver = ClientCertResolver::new(rustls::crypto::aws_lc_rs::default_provider())
+///     .with_pem_files(
+///         "/etc/certs/payments.pem",
+///         "/etc/certs/payments.key",
+///         &["*.payments.example.com"],
+///     )?
+///     .with_pem_files("/etc/certs/default.pem", "/etc/certs/default.key", &[])?;
+///
+/// // Later, once the certificates have been renewed
+/// resolver.reload()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ClientCertResolver {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    provider: Arc<CryptoProvider>,
+    identities: Arc<Identities>,
+    /// The resolvers handed out for each server
+    ///
+    /// rustls only resumes sessions made with the same resolver instance, so
+    /// these are kept as long as sessions for the server are likely to be.
+    servers: Mutex<Servers>,
+}
+
+/// The resolvers of the most recently used servers
+///
+/// This holds as many servers as the default session cache of rustls, and
+/// evicts the least recently used one when full.
+#[derive(Default)]
+struct Servers {
+    resolvers: HashMap<String, (Arc<ServerCertResolver>, u64)>,
+    uses: u64,
+}
+
+impl Servers {
+    fn get_or_insert(
+        &mut self,
+        server_name: String,
+        identities: &Arc<Identities>,
+    ) -> Arc<ServerCertResolver> {
+        self.uses += 1;
+        if let Some((resolver, last_use)) = self.resolvers.get_mut(&server_name) {
+            *last_use = self.uses;
+            return resolver.clone();
+        }
+
+        if self.resolvers.len() >= MAX_SERVERS {
+            let oldest = self
+                .resolvers
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(server_name, _)| server_name.clone());
+            if let Some(oldest) = oldest {
+                self.resolvers.remove(&oldest);
+            }
+        }
+
+        let resolver = Arc::new(ServerCertResolver {
+            identities: identities.clone(),
+            server_name: server_name.clone(),
+        });
+        self.resolvers
+            .insert(server_name, (resolver.clone(), self.uses));
+        resolver
+    }
+}
+
+/// The number of servers rustls keeps sessions for by default
+const MAX_SERVERS: usize = 256;
+
+impl ClientCertResolver {
+    /// Creates a resolver without identities
+    ///
+    /// The private keys of the identities are loaded with `provider`.
+    pub fn new(provider: impl Into<Arc<CryptoProvider>>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                provider: provider.into(),
+                identities: Arc::default(),
+                servers: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Adds an identity, sent only to `hosts` if any are given
+    ///
+    /// Hosts are compared case-insensitively, and may be wildcard patterns
+    /// like `*.example.com`. This fails if the key does not match the
+    /// certificate, or cannot be used with the resolver's provider.
+    pub fn with_identity(
+        self,
+        identity: ClientIdentity,
+        hosts: &[&str],
+    ) -> Result<Self, io::Error> {
+        let identity = Identity::new(&identity, None, hosts, &self.inner.provider)?;
+        self.push(identity);
+        Ok(self)
+    }
+
+    /// Adds an identity loaded from PEM files, sent only to `hosts` if any
+    /// are given
+    ///
+    /// The files are read again by [`reload()`](Self::reload). See
+    /// [`ClientIdentity::from_pem_files()`] and
+    /// [`with_identity()`](Self::with_identity).
+    pub fn with_pem_files(
+        self,
+        cert_chain: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        hosts: &[&str],
+    ) -> Result<Self, io::Error> {
+        let files = Files {
+            cert_chain: cert_chain.as_ref().to_owned(),
+            key: key.as_ref().to_owned(),
+        };
+        let identity = Identity::load(files, hosts, &self.inner.provider)?;
+        self.push(identity);
+        Ok(self)
+    }
+
+    fn push(&self, identity: Identity) {
+        let mut identities = self
+            .inner
+            .identities
+            .0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        Arc::make_mut(&mut identities).push(identity);
+    }
+
+    /// Reads all identities added with [`with_pem_files()`](Self::with_pem_files) again
+    ///
+    /// The identities are replaced all at once, and only if every one of them
+    /// could be loaded: on error, the current identities are kept.
+    /// Connections that are already established are unaffected.
+    pub fn reload(&self) -> Result<(), io::Error> {
+        let current = self.load();
+        let mut reloaded = Vec::with_capacity(current.len());
+        for identity in current.iter() {
+            reloaded.push(match &identity.files {
+                Some(files) => Identity::load(
+                    files.clone(),
+                    &identity.hosts_patterns(),
+                    &self.inner.provider,
+                )?,
+                None => identity.clone(),
+            });
+        }
+
+        let mut identities = self
+            .inner
+            .identities
+            .0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Identities added while reloading are kept
+        reloaded.extend(
+            identities[current.len()..]
+                .iter()
+                .cloned(),
+        );
+        *identities = Arc::new(reloaded);
+        crate::log::debug!("reloaded {} client identities", identities.len());
+        Ok(())
+    }
+
+    /// Returns a resolver restricted to the identities suitable for `server_name`
+    pub(crate) fn for_server(&self, server_name: &ServerName<'_>) -> Arc<dyn ResolvesClientCert> {
+        let server_name = server_name
+            .to_str()
+            .to_ascii_lowercase();
+        self.inner
+            .servers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert(server_name, &self.inner.identities)
+    }
+
+    fn load(&self) -> Arc<Vec<Identity>> {
+        self.inner.identities.load()
+    }
+}
+
+impl ResolvesClientCert for ClientCertResolver {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.inner
+            .identities
+            .resolve(None, root_hint_subjects, sigschemes)
+    }
+
+    fn has_certs(&self) -> bool {
+        self.load()
+            .iter()
+            .any(|identity| identity.hosts.is_empty())
+    }
+}
+
+impl fmt::Debug for ClientCertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCertResolver")
+            .field("identities", &self.load().len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Default)]
+struct Identities(RwLock<Arc<Vec<Identity>>>);
+
+impl Identities {
+    fn load(&self) -> Arc<Vec<Identity>> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn resolve(
+        &self,
+        server_name: Option<&str>,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        let identities = self.load();
+        let (_, identity) = identities
+            .iter()
+            .filter_map(|identity| Some((identity.host_specificity(server_name)?, identity)))
+            .filter(|(_, identity)| identity.accepts(root_hint_subjects, sigschemes))
+            // Keep the first of equally specific identities
+            .rev()
+            .max_by_key(|(specificity, _)| *specificity)?;
+        Some(identity.key.clone())
+    }
+}
+
+impl fmt::Debug for Identities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.load().iter())
+            .finish()
+    }
+}
+
+/// The identities of a [`ClientCertResolver`] suitable for one server
+#[derive(Debug)]
+struct ServerCertResolver {
+    identities: Arc<Identities>,
+    server_name: String,
+}
+
+impl ResolvesClientCert for ServerCertResolver {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.identities
+            .resolve(Some(&self.server_name), root_hint_subjects, sigschemes)
+    }
+
+    fn has_certs(&self) -> bool {
+        self.identities
+            .load()
+            .iter()
+            .any(|identity| {
+                identity
+                    .host_specificity(Some(&self.server_name))
+                    .is_some()
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Files {
+    cert_chain: PathBuf,
+    key: PathBuf,
+}
+
+#[derive(Clone)]
+struct Identity {
+    key: Arc<CertifiedKey>,
+    /// The issuers and subjects of the chain's certificates
+    names: Vec<DistinguishedName>,
+    hosts: Vec<(String, Host)>,
+    files: Option<Files>,
+}
+
+impl Identity {
+    fn load(files: Files, hosts: &[&str], provider: &CryptoProvider) -> Result<Self, io::Error> {
+        let identity = ClientIdentity::from_pem_files(&files.cert_chain, &files.key)?;
+        Self::new(&identity, Some(files), hosts, provider)
+    }
+
+    fn new(
+        identity: &ClientIdentity,
+        files: Option<Files>,
+        hosts: &[&str],
+        provider: &CryptoProvider,
+    ) -> Result<Self, io::Error> {
+        let key = identity.certified_key(provider)?;
+        let (end_entity, intermediates) = identity
+            .cert_chain()
+            .split_first()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "client certificate chain is empty",
+                )
+            })?;
+        let end_entity = webpki::EndEntityCert::try_from(end_entity).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid client certificate: {err}"),
+            )
+        })?;
+        let mut names = vec![DistinguishedName::in_sequence(end_entity.issuer())];
+        for cert in intermediates {
+            // CA certificates are parsed leniently, as for trust anchors
+            if let Ok(anchor) = webpki::anchor_from_trusted_cert(cert) {
+                names.push(DistinguishedName::in_sequence(&anchor.subject));
+            }
+            if let Ok(cert) = webpki::EndEntityCert::try_from(cert) {
+                names.push(DistinguishedName::in_sequence(cert.issuer()));
+            }
+        }
+
+        Ok(Self {
+            key: Arc::new(key),
+            names,
+            hosts: hosts
+                .iter()
+                .map(|host| (host.to_string(), Host::parse(host)))
+                .collect(),
+            files,
+        })
+    }
+
+    fn hosts_patterns(&self) -> Vec<&str> {
+        self.hosts
+            .iter()
+            .map(|(pattern, _)| pattern.as_str())
+            .collect()
+    }
+
+    /// Returns how specifically this identity targets `server_name`, if it
+    /// can be sent to it
+    fn host_specificity(&self, server_name: Option<&str>) -> Option<usize> {
+        if self.hosts.is_empty() {
+            return Some(0);
+        }
+        let server_name = server_name?;
+        self.hosts
+            .iter()
+            .filter_map(|(_, host)| host.matches(server_name))
+            .max()
+            .map(|specificity| specificity.saturating_add(1))
+    }
+
+    fn accepts(&self, root_hint_subjects: &[&[u8]], sigschemes: &[SignatureScheme]) -> bool {
+        let issuer_ok = root_hint_subjects.is_empty()
+            || self.names.iter().any(|name| {
+                root_hint_subjects
+                    .iter()
+                    .any(|hint| *hint == name.as_ref())
+            });
+        issuer_ok
+            && self
+                .key
+                .key
+                .choose_scheme(sigschemes)
+                .is_some()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("hosts", &self.hosts_patterns())
+            .field("files", &self.files)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::fs;
+
+    use rustls::ClientConfig;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const CERTS: &str = "examples/sample.pem";
+    const KEY: &str = "examples/sample.rsa";
+
+    #[test]
+    fn selects_by_host() {
+        let resolver = resolver()
+            .with_pem_files(CERTS, KEY, &[])
+            .unwrap()
+            .with_pem_files(CERTS, KEY, &["*.example.com"])
+            .unwrap()
+            .with_pem_files(CERTS, KEY, &["api.example.com"])
+            .unwrap();
+        let [any, wildcard, exact] = keys(&resolver);
+
+        let cases = [
+            ("api.example.com", &exact),
+            ("API.example.com", &exact),
+            ("www.example.com", &wildcard),
+            ("example.com", &any),
+            ("localhost", &any),
+        ];
+        for (host, expected) in cases {
+            let server_name = ServerName::try_from(host).unwrap();
+            let key = resolver
+                .for_server(&server_name)
+                .resolve(&[], SCHEMES)
+                .unwrap();
+            assert!(Arc::ptr_eq(&key, expected), "{host}");
+        }
+
+        let key = resolver.resolve(&[], SCHEMES).unwrap();
+        assert!(Arc::ptr_eq(&key, &any));
+    }
+
+    #[test]
+    fn restricted_identities() {
+        let resolver = resolver()
+            .with_pem_files(CERTS, KEY, &["localhost"])
+            .unwrap();
+        assert!(!resolver.has_certs());
+        assert!(resolver.resolve(&[], SCHEMES).is_none());
+
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let scoped = resolver.for_server(&server_name);
+        assert!(scoped.has_certs());
+        assert!(scoped.resolve(&[], SCHEMES).is_some());
+        let other = ServerName::try_from("example.com").unwrap();
+        assert!(!resolver.for_server(&other).has_certs());
+    }
+
+    #[test]
+    fn selects_by_issuer_and_scheme() {
+        let resolver = resolver()
+            .with_pem_files(CERTS, KEY, &[])
+            .unwrap();
+        let roots = {
+            let mut roots = rustls::RootCertStore::empty();
+            let certs = ClientIdentity::from_pem_files(CERTS, KEY).unwrap();
+            roots
+                .add(certs.cert_chain()[2].clone())
+                .unwrap();
+            roots
+        };
+        let ca = roots.subjects().pop().unwrap();
+
+        assert!(resolver
+            .resolve(&[ca.as_ref()], SCHEMES)
+            .is_some());
+        assert!(resolver
+            .resolve(&[b"\x30\x00"], SCHEMES)
+            .is_none());
+        assert!(resolver
+            .resolve(&[], &[SignatureScheme::ED25519])
+            .is_none());
+    }
+
+    #[test]
+    fn reload() {
+        let temp = TempDir::new("client-cert");
+        let dir = temp.path();
+        let (certs, key) = (dir.join("client.pem"), dir.join("client.key"));
+        fs::copy(CERTS, &certs).unwrap();
+        fs::copy(KEY, &key).unwrap();
+
+        let resolver = resolver()
+            .with_pem_files(&certs, &key, &["localhost"])
+            .unwrap()
+            .with_identity(ClientIdentity::from_pem_files(CERTS, KEY).unwrap(), &[])
+            .unwrap();
+        let clone = resolver.clone();
+        let [before, unchanged] = keys(&resolver);
+
+        resolver.reload().unwrap();
+        let [after, still_unchanged] = keys(&clone);
+        assert!(!Arc::ptr_eq(&before, &after));
+        assert!(Arc::ptr_eq(&unchanged, &still_unchanged));
+        assert_eq!(resolver.load()[0].hosts_patterns(), &["localhost"]);
+
+        // A failed reload keeps the current identities
+        fs::write(&key, b"").unwrap();
+        assert!(resolver.reload().is_err());
+        let [current, _] = keys(&resolver);
+        assert!(Arc::ptr_eq(&current, &after));
+    }
+
+    const SCHEMES: &[SignatureScheme] = &[
+        SignatureScheme::RSA_PSS_SHA256,
+        SignatureScheme::RSA_PKCS1_SHA256,
+    ];
+
+    #[test]
+    fn evicts_least_recently_used_servers() {
+        let resolver = resolver();
+        let name = |i: usize| ServerName::try_from(format!("host{i}.example.com")).unwrap();
+        let first = resolver.for_server(&name(0));
+        let second = resolver.for_server(&name(1));
+        for i in 2..MAX_SERVERS {
+            resolver.for_server(&name(i));
+        }
+        // Both fit, and using the first one again keeps it over the second
+        assert!(Arc::ptr_eq(&resolver.for_server(&name(0)), &first));
+        resolver.for_server(&name(MAX_SERVERS));
+
+        let servers = resolver
+            .inner
+            .servers
+            .lock()
+            .unwrap()
+            .resolvers
+            .len();
+        assert_eq!(servers, MAX_SERVERS);
+        assert!(Arc::ptr_eq(&resolver.for_server(&name(0)), &first));
+        assert!(!Arc::ptr_eq(&resolver.for_server(&name(1)), &second));
+    }
+
+    fn keys<const N: usize>(resolver: &ClientCertResolver) -> [Arc<CertifiedKey>; N] {
+        let identities = resolver.load();
+        std::array::from_fn(|i| identities[i].key.clone())
+    }
+
+    fn resolver() -> ClientCertResolver {
+        ClientCertResolver::new(
+            ClientConfig::builder()
+                .crypto_provider()
+                .clone(),
+        )
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+use std::task::{Context, Poll};
+
+use http::Request;
+use hyper::body::Body;
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::{Builder, Client, Error, ResponseFuture};
+use tower_service::Service;
+
+use super::HttpsConnector;
+use crate::error::BoxError;
+
+/// Marks a request as safe to send as TLS 1.3 early data (0-RTT)
+///
+/// Insert it into the extensions of requests sent through an
+/// [`EarlyDataClient`]. Early data can be replayed by an attacker, so only
+/// mark idempotent requests without side effects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllowEarlyData;
+
+/// A client sending the requests marked with [`AllowEarlyData`] as TLS 1.3
+/// early data
+///
+/// The connector only sees the destination of each connection, so the
+/// client keeps two pools over clones of the same [`HttpsConnector`]: one
+/// with [early data](HttpsConnector::set_early_data) enabled for the marked
+/// requests, and one without for all other requests. A connection opened
+/// for a marked request is only ever reused by marked requests. Both share
+/// the TLS configuration, and with it the sessions they resume.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(all(feature = "rustls-native-certs", feature = "http1"))]
+/// # async fn doc() -> Result<(), Box<dyn std::error::Error>> {
+/// use http::Request;
+/// use http_body_util::Empty;
+/// use hyper::body::Bytes;
+/// use hyper_rustls::{AllowEarlyData, EarlyDataClient, HttpsConnectorBuilder};
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+///
+/// let https = HttpsConnectorBuilder::new()
+///     .with_native_roots()?
+///     .https_only()
+///     .enable_http1()
+///     .build();
+/// let client = EarlyDataClient::new(&Client::builder(TokioExecutor::new()), https);
+///
+/// let mut request = Request::get("https://example.com/").body(Empty::<Bytes>::new())?;
+/// request.extensions_mut().insert(AllowEarlyData);
+/// let response = client.request(request).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EarlyDataClient<T, B> {
+    early: Client<HttpsConnector<T>, B>,
+    regular: Client<HttpsConnector<T>, B>,
+}
+
+impl<T, B> EarlyDataClient<T, B>
+where
+    HttpsConnector<T>: Connect + Clone,
+    B: Body + Send,
+    B::Data: Send,
+{
+    /// Builds the two clients from `builder`, over clones of `connector`
+    ///
+    /// Whether `connector` has early data enabled does not matter.
+    pub fn new(builder: &Builder, connector: HttpsConnector<T>) -> Self {
+        let mut early = connector.clone();
+        early.set_early_data(true);
+        let mut regular = connector;
+        regular.set_early_data(false);
+        Self {
+            early: builder.build(early),
+            regular: builder.build(regular),
+        }
+    }
+}
+
+impl<T, B> EarlyDataClient<T, B>
+where
+    HttpsConnector<T>: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    /// Sends `request`, as early data if it is marked with [`AllowEarlyData`]
+    pub fn request(&self, request: Request<B>) -> ResponseFuture {
+        match request
+            .extensions()
+            .get::<AllowEarlyData>()
+        {
+            Some(_) => self.early.request(request),
+            None => self.regular.request(request),
+        }
+    }
+}
+
+impl<T, B> Service<Request<B>> for EarlyDataClient<T, B>
+where
+    HttpsConnector<T>: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = http::Response<hyper::body::Incoming>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        self.request(request)
+    }
+}
+
+impl<T: Clone, B> Clone for EarlyDataClient<T, B> {
+    fn clone(&self) -> Self {
+        Self {
+            early: self.early.clone(),
+            regular: self.regular.clone(),
+        }
+    }
+}
+
+impl<T, B> fmt::Debug for EarlyDataClient<T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EarlyDataClient")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "http1", any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    use http::Request;
+    use http_body_util::Empty;
+    use hyper::body::Bytes;
+    use hyper_util::rt::TokioExecutor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{AllowEarlyData, EarlyDataClient};
+    use crate::test_util::{server_config, trusting_config};
+    use crate::HttpsConnectorBuilder;
+
+    #[tokio::test]
+    async fn only_marked_requests_are_sent_as_early_data() {
+        let mut config = server_config();
+        config.max_early_data_size = 1024;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Answers one request per connection, reporting whether it came as early data
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (acceptor, tx) = (acceptor.clone(), tx.clone());
+                tokio::spawn(async move {
+                    let Ok(mut tls) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = Vec::new();
+                    if let Some(mut reader) = tls.get_mut().1.early_data() {
+                        std::io::Read::read_to_end(&mut reader, &mut request).unwrap();
+                    }
+                    let early = !request.is_empty();
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = tls.read(&mut buf).await.unwrap();
+                        assert_ne!(n, 0);
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    tx.send(early).unwrap();
+                    tls.write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                    tls.shutdown().await.unwrap();
+                });
+            }
+        });
+
+        let https = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let client = EarlyDataClient::new(
+            &hyper_util::client::legacy::Client::builder(TokioExecutor::new()),
+            https,
+        );
+        let request = |allow| {
+            let mut request = Request::get(format!("https://localhost:{port}/"))
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            if allow {
+                request
+                    .extensions_mut()
+                    .insert(AllowEarlyData);
+            }
+            request
+        };
+
+        // The first connection stores sessions to resume
+        for (allow, early) in [(true, false), (true, true), (false, false)] {
+            let response = client
+                .request(request(allow))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(rx.recv().await.unwrap(), early);
+        }
+    }
+}
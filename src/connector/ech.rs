@@ -0,0 +1,216 @@
+use std::fmt;
+use std::sync::Arc;
+
+use http::Uri;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::{EchConfig, EchGreaseConfig, EchMode};
+use rustls::crypto::hpke::Hpke;
+use rustls::pki_types::EchConfigListBytes;
+use rustls::ClientConfig;
+
+/// A trait implemented by types that can look up the ECH configurations of a
+/// destination
+///
+/// ECH configurations are usually published in the `ech` parameter of the
+/// destination's `HTTPS` DNS record. Lookups are synchronous, so they would
+/// typically be answered from a cache filled ahead of time.
+pub trait ResolveEchConfig {
+    /// Returns the ECH configuration list for `uri`, or `None` if it has none
+    fn resolve(&self, uri: &Uri) -> Option<EchConfigListBytes<'static>>;
+}
+
+impl<F> ResolveEchConfig for F
+where
+    F: Fn(&Uri) -> Option<EchConfigListBytes<'static>>,
+{
+    fn resolve(&self, uri: &Uri) -> Option<EchConfigListBytes<'static>> {
+        self(uri)
+    }
+}
+
+/// Encrypted Client Hello (ECH) settings for an [`HttpsConnector`](crate::HttpsConnector)
+///
+/// ECH encrypts the ClientHello, including the server name, with a key
+/// published by the destination, so that on-path observers only see the
+/// public name from its ECH configuration. Destinations without an ECH
+/// configuration can be offered GREASE ECH instead, which looks like ECH on
+/// the wire but does not hide anything.
+///
+/// Connections offering ECH only use TLS 1.3, and are verified by the
+/// verifier given here rather than by the one of the connector's
+/// configuration, since rustls cannot add ECH to an existing configuration.
+/// All other settings of the connector's configuration are kept.
+///
+/// The HPKE suites default to those of the aws-lc-rs provider, if the
+/// `aws-lc-rs` feature is enabled. Otherwise they have to be set with
+/// [`with_hpke_suites()`](Self::with_hpke_suites).
+///
+/// Whether ECH was accepted is reported by [`TlsInfo::ech_status()`](crate::TlsInfo::ech_status).
+/// If the destination rejects ECH, the handshake fails; the error carries
+/// the retry configurations sent by the server, if any.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "http1")]
+/// # fn doc(verifier: std::sync::Arc<dyn rustls::client::danger::ServerCertVerifier>, config: rustls::ClientConfig) {
+/// use hyper_rustls::{Ech, HttpsConnectorBuilder};
+/// use rustls::pki_types::EchConfigListBytes;
+///
+/// // Configurations taken from HTTPS DNS records, keyed by host
+/// let configs = std::collections::HashMap::<String, Vec<u8>>::new();
+/// let ech = Ech::with_resolver(
+///     move |uri: &http::Uri| {
+///         let list = configs.get(uri.host()?)?;
+///         Some(EchConfigListBytes::from(list.clone()))
+///     },
+///     verifier,
+/// )
+/// .with_grease(true);
+///
+/// let https = HttpsConnectorBuilder::new()
+///     .with_tls_config(config)
+///     .https_only()
+///     .with_ech(ech)
+///     .enable_http1()
+///     .build();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Ech {
+    source: Source,
+    grease: bool,
+    verifier: Arc<dyn ServerCertVerifier>,
+    hpke_suites: &'static [&'static dyn Hpke],
+}
+
+impl Ech {
+    /// Offers ECH with `config_list` to every destination
+    pub fn new(
+        config_list: EchConfigListBytes<'static>,
+        verifier: Arc<dyn ServerCertVerifier>,
+    ) -> Self {
+        Self::with_source(Source::Static(config_list), verifier)
+    }
+
+    /// Offers ECH with the configurations found by `resolver`
+    ///
+    /// Destinations for which `resolver` finds nothing are connected to
+    /// without ECH, unless [GREASE](Self::with_grease) is enabled.
+    pub fn with_resolver(
+        resolver: impl ResolveEchConfig + Send + Sync + 'static,
+        verifier: Arc<dyn ServerCertVerifier>,
+    ) -> Self {
+        Self::with_source(Source::Resolver(Arc::new(resolver)), verifier)
+    }
+
+    /// Offers GREASE ECH to every destination
+    pub fn grease(verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        Self::with_source(Source::None, verifier).with_grease(true)
+    }
+
+    fn with_source(source: Source, verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        Self {
+            source,
+            grease: false,
+            verifier,
+            #[cfg(feature = "aws-lc-rs")]
+            hpke_suites: rustls::crypto::aws_lc_rs::hpke::ALL_SUPPORTED_SUITES,
+            #[cfg(not(feature = "aws-lc-rs"))]
+            hpke_suites: &[],
+        }
+    }
+
+    /// Whether to offer GREASE ECH to destinations without an ECH configuration
+    ///
+    /// This is disabled by default, except for [`Ech::grease()`].
+    pub fn with_grease(mut self, enabled: bool) -> Self {
+        self.grease = enabled;
+        self
+    }
+
+    /// Sets the HPKE suites used to encrypt the ClientHello
+    ///
+    /// An ECH configuration is only used if it supports one of these suites.
+    /// GREASE ECH uses the first one.
+    pub fn with_hpke_suites(mut self, suites: &'static [&'static dyn Hpke]) -> Self {
+        self.hpke_suites = suites;
+        self
+    }
+
+    /// Derives the configuration for connecting to `uri` from `base`, or
+    /// returns `None` if ECH is not offered to it
+    pub(crate) fn configure(
+        &self,
+        uri: &Uri,
+        base: &ClientConfig,
+    ) -> Result<Option<ClientConfig>, rustls::Error> {
+        let config_list = match &self.source {
+            Source::Static(config_list) => Some(config_list.clone()),
+            Source::Resolver(resolver) => resolver.resolve(uri),
+            Source::None => None,
+        };
+        let mode = match config_list {
+            Some(config_list) => EchMode::Enable(EchConfig::new(config_list, self.hpke_suites)?),
+            None if self.grease => EchMode::Grease(self.grease_config()?),
+            None => return Ok(None),
+        };
+
+        let mut config = ClientConfig::builder_with_provider(base.crypto_provider().clone())
+            .with_ech(mode)?
+            .dangerous()
+            .with_custom_certificate_verifier(self.verifier.clone())
+            .with_client_cert_resolver(base.client_auth_cert_resolver.clone());
+        config.alpn_protocols = base.alpn_protocols.clone();
+        config.check_selected_alpn = base.check_selected_alpn;
+        config.resumption = base.resumption.clone();
+        config.max_fragment_size = base.max_fragment_size;
+        config.enable_sni = base.enable_sni;
+        config.key_log = base.key_log.clone();
+        config.enable_secret_extraction = base.enable_secret_extraction;
+        config.enable_early_data = base.enable_early_data;
+        config.time_provider = base.time_provider.clone();
+        config.cert_decompressors = base.cert_decompressors.clone();
+        config.cert_compressors = base.cert_compressors.clone();
+        config.cert_compression_cache = base.cert_compression_cache.clone();
+        config.send_ticket_request = base.send_ticket_request;
+        Ok(Some(config))
+    }
+
+    /// A GREASE configuration with a fresh placeholder key
+    fn grease_config(&self) -> Result<EchGreaseConfig, rustls::Error> {
+        let suite = self
+            .hpke_suites
+            .first()
+            .ok_or_else(|| rustls::Error::General("no HPKE suites available for ECH".into()))?;
+        let (placeholder_key, _) = suite.generate_key_pair()?;
+        Ok(EchGreaseConfig::new(*suite, placeholder_key))
+    }
+}
+
+impl fmt::Debug for Ech {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ech")
+            .field("source", &self.source)
+            .field("grease", &self.grease)
+            .field("hpke_suites", &self.hpke_suites.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone)]
+enum Source {
+    Static(EchConfigListBytes<'static>),
+    Resolver(Arc<dyn ResolveEchConfig + Send + Sync>),
+    None,
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Static(_) => f.write_str("Static"),
+            Self::Resolver(_) => f.write_str("Resolver"),
+            Self::None => f.write_str("None"),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use rustls::ClientConfig;
+
+/// A shared handle to the TLS configuration of an [`HttpsConnector`](crate::HttpsConnector)
+///
+/// The handle is shared between a connector and all its clones, such as the
+/// ones held by a hyper `Client`. Replacing the configuration with
+/// [`store()`](Self::store) affects every connection made afterwards, while
+/// connections that are already established (for example, pooled ones) keep
+/// using the configuration they were made with.
+///
+/// This allows rotating client certificates or root stores without
+/// rebuilding the client and losing its connection pool.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "rustls-native-certs")]
+/// # fn doc(new_config: rustls::ClientConfig) -> std::io::Result<()> {
+/// let https = hyper_rustls::HttpsConnectorBuilder::new()
+///     .with_native_roots()?
+///     .https_only()
+///     .enable_http1()
+///     .build();
+/// let handle = https.tls_config_handle().clone();
+///
+/// // Later, once the certificates have been rotated
+/// handle.store(new_config);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ClientConfigHandle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: RwLock<Arc<ClientConfig>>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl ClientConfigHandle {
+    /// Creates a handle, remembering the config's ALPN protocols for later replacements
+    pub(crate) fn new(config: Arc<ClientConfig>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                alpn_protocols: config.alpn_protocols.clone(),
+                config: RwLock::new(config),
+            }),
+        }
+    }
+
+    /// Returns the configuration used for new connections
+    pub fn load(&self) -> Arc<ClientConfig> {
+        self.inner
+            .config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the configuration used for new connections
+    ///
+    /// The [`alpn_protocols`](ClientConfig::alpn_protocols) field is
+    /// overwritten with the protocols of the configuration the connector was
+    /// built with, so that they keep matching the enabled HTTP versions.
+    pub fn store(&self, mut config: ClientConfig) {
+        config
+            .alpn_protocols
+            .clone_from(&self.inner.alpn_protocols);
+        *self
+            .inner
+            .config
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    }
+
+    /// The ALPN protocols offered by the connector
+    pub(crate) fn alpn_protocols(&self) -> &[Vec<u8>] {
+        &self.inner.alpn_protocols
+    }
+
+    /// Rewrites the ALPN protocols of `config` to match the connector's, if needed
+    pub(crate) fn apply_alpn(&self, config: Arc<ClientConfig>) -> Arc<ClientConfig> {
+        if config.alpn_protocols == self.inner.alpn_protocols {
+            return config;
+        }
+
+        let mut config = Arc::unwrap_or_clone(config);
+        config
+            .alpn_protocols
+            .clone_from(&self.inner.alpn_protocols);
+        Arc::new(config)
+    }
+}
+
+impl fmt::Debug for ClientConfigHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientConfigHandle")
+            .field("alpn_protocols", &self.inner.alpn_protocols)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ClientConfigHandle;
+
+    #[test]
+    fn store_keeps_alpn_protocols() {
+        let mut initial = config();
+        initial.alpn_protocols = vec![b"h2".to_vec()];
+        let handle = ClientConfigHandle::new(Arc::new(initial));
+
+        let mut replacement = config();
+        replacement.enable_sni = false;
+        replacement.alpn_protocols = vec![b"fancyprotocol".to_vec()];
+        handle.store(replacement);
+
+        let current = handle.load();
+        assert!(!current.enable_sni);
+        assert_eq!(&current.alpn_protocols, &[b"h2".to_vec()]);
+    }
+
+    #[test]
+    fn apply_alpn() {
+        let mut initial = config();
+        initial.alpn_protocols = vec![b"h2".to_vec()];
+        let handle = ClientConfigHandle::new(Arc::new(initial));
+
+        let routed = Arc::new(config());
+        let applied = handle.apply_alpn(routed.clone());
+        assert!(routed.alpn_protocols.is_empty());
+        assert_eq!(&applied.alpn_protocols, &[b"h2".to_vec()]);
+
+        // Configs that already match are used as they are
+        assert!(Arc::ptr_eq(&handle.apply_alpn(applied.clone()), &applied));
+    }
+
+    fn config() -> rustls::ClientConfig {
+        rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth()
+    }
+}
@@ -0,0 +1,672 @@
+use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use std::{fmt, io};
+
+use bytes::Bytes;
+use h3::client::SendRequest;
+use h3_quinn::OpenStreams;
+use http::uri::Scheme;
+use http::Uri;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::Endpoint;
+use tokio::time::Instant;
+use tower_service::Service;
+
+use super::alt_svc::AltSvcCache;
+use super::client_cert::ClientCertResolver;
+use super::handle::ClientConfigHandle;
+use super::{select_tls_config, ResolveClientConfig, ResolveServerName};
+use crate::error::BoxError;
+use crate::Error;
+
+#[cfg(not(any(feature = "ring", feature = "aws-lc-rs")))]
+compile_error!("the `http3` feature requires the `ring` or `aws-lc-rs` feature");
+
+/// A connector for HTTP/3 over QUIC, for the `https` scheme
+///
+/// This is built with
+/// [`enable_http3()`](crate::HttpsConnectorBuilder::enable_http3), and uses
+/// the TLS configuration, [`ResolveClientConfig`], [`ClientCertResolver`],
+/// [`ResolveServerName`] and handshake timeout set up on the builder, the
+/// same way as an [`HttpsConnector`](crate::HttpsConnector). The only ALPN
+/// protocol offered is `h3`.
+///
+/// Connections can also be made to the `h3` alternative services an origin
+/// advertised, see [`call_alt_svc()`](Self::call_alt_svc).
+///
+/// hyper does not speak HTTP/3, so connections are returned as
+/// [`Http3Connection`]s, which send requests with the `h3` crate.
+///
+/// The addresses of the destination are tried in turn, and the handshake
+/// timeout covers all the attempts together. The UDP sockets used for QUIC
+/// are bound on the first connection to an IPv4 or IPv6 address, and are
+/// shared with all clones of the connector. This has to happen within a
+/// Tokio runtime.
+///
+/// # Stability
+///
+/// The `http3` feature is unstable: [`Http3Connection`] exposes types of the
+/// `h3` and `h3-quinn` crates, which have not reached a stable release yet,
+/// so upgrading them in a minor release of this crate may break code using
+/// this feature.
+#[derive(Clone)]
+pub struct Http3Connector {
+    tls_config: ClientConfigHandle,
+    config_resolver: Option<Arc<dyn ResolveClientConfig + Sync + Send>>,
+    client_cert_resolver: Option<ClientCertResolver>,
+    server_name_resolver: Arc<dyn ResolveServerName + Sync + Send>,
+    handshake_timeout: Option<Duration>,
+    endpoints: Arc<Endpoints>,
+}
+
+impl Http3Connector {
+    pub(crate) fn new(
+        tls_config: ClientConfigHandle,
+        config_resolver: Option<Arc<dyn ResolveClientConfig + Sync + Send>>,
+        client_cert_resolver: Option<ClientCertResolver>,
+        server_name_resolver: Arc<dyn ResolveServerName + Sync + Send>,
+        handshake_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            tls_config,
+            config_resolver,
+            client_cert_resolver,
+            server_name_resolver,
+            handshake_timeout,
+            endpoints: Arc::default(),
+        }
+    }
+
+    /// The handle to the TLS configuration used for new connections.
+    ///
+    /// See [`ClientConfigHandle`].
+    pub fn tls_config_handle(&self) -> &ClientConfigHandle {
+        &self.tls_config
+    }
+}
+
+impl Service<Uri> for Http3Connector {
+    type Response = Http3Connection;
+    type Error = BoxError;
+
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Http3Connection, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        self.call_via(dst.clone(), dst)
+    }
+}
+
+impl Http3Connector {
+    /// Connects to `origin` through the HTTP/3 alternative service cached for it
+    ///
+    /// Returns `None` if `cache` holds no `h3` alternative for `origin`.
+    /// Otherwise, the QUIC connection is made to the alternative's endpoint,
+    /// while the TLS handshake still verifies the origin's name, and if it
+    /// fails, the alternative is removed from the cache. In both cases,
+    /// connect to the origin over TCP instead, for example with an
+    /// [`AltSvcConnector`](crate::AltSvcConnector) sharing the same cache.
+    pub fn call_alt_svc(
+        &self,
+        origin: Uri,
+        cache: &AltSvcCache,
+    ) -> Option<<Self as Service<Uri>>::Future> {
+        let (alternative, target) = cache
+            .alternatives(&origin)
+            .into_iter()
+            .filter(|alternative| alternative.protocol() == b"h3")
+            .find_map(|alternative| {
+                let target = alternative.target(&origin)?;
+                Some((alternative, target))
+            })?;
+
+        let via_alternative = self.call_via(origin.clone(), target);
+        let cache = cache.clone();
+        Some(Box::pin(async move {
+            let result = via_alternative.await;
+            if let Err(e) = &result {
+                crate::log::debug!("HTTP/3 alternative service for {origin} failed: {e}");
+                cache.mark_broken(&origin, &alternative);
+            }
+            result
+        }))
+    }
+
+    /// Connects to `dst` through the endpoint of `target`
+    ///
+    /// `target` is `dst` itself, unless connecting to an alternative service
+    /// for it. The server name and TLS configuration are derived from `dst`.
+    fn call_via(&self, dst: Uri, target: Uri) -> <Self as Service<Uri>>::Future {
+        match dst.scheme() {
+            Some(scheme) if scheme != &Scheme::HTTPS => {
+                let err = Error::UnsupportedScheme(scheme.clone());
+                return Box::pin(async move { Err(err.into()) });
+            }
+            Some(_) => {}
+            None => return Box::pin(async move { Err(Error::MissingScheme.into()) }),
+        }
+
+        let hostname = match self.server_name_resolver.resolve(&dst) {
+            Ok(hostname) => hostname,
+            Err(e) => {
+                return Box::pin(async move { Err(Error::ServerName(e).into()) });
+            }
+        };
+        let cfg = select_tls_config(
+            &self.tls_config,
+            self.config_resolver.as_deref(),
+            self.client_cert_resolver.as_ref(),
+            &dst,
+            &hostname,
+        );
+        let quic_config = match QuicClientConfig::try_from(cfg) {
+            Ok(quic_config) => quinn::ClientConfig::new(Arc::new(quic_config)),
+            Err(e) => return Box::pin(async move { Err(Error::Quic(e.into()).into()) }),
+        };
+
+        let endpoints = self.endpoints.clone();
+        let handshake_timeout = self.handshake_timeout;
+        Box::pin(async move {
+            let addrs = resolve(&target)
+                .await
+                .map_err(|e| Error::Connect(e.into()))?;
+            let server_name = hostname.to_str();
+            connect(
+                &endpoints,
+                addrs,
+                &quic_config,
+                &server_name,
+                handshake_timeout,
+            )
+            .await
+        })
+    }
+}
+
+/// Connects to each address in turn, returning the first connection made
+///
+/// The handshake timeout covers all the attempts together. If all attempts
+/// fail, the last error is returned.
+async fn connect(
+    endpoints: &Endpoints,
+    addrs: Vec<SocketAddr>,
+    quic_config: &quinn::ClientConfig,
+    server_name: &str,
+    handshake_timeout: Option<Duration>,
+) -> Result<Http3Connection, BoxError> {
+    let deadline = handshake_timeout.map(|timeout| Instant::now() + timeout);
+    let mut last_err = None;
+    for addr in addrs {
+        match connect_to(endpoints, addr, quic_config, server_name, deadline).await {
+            Ok(connection) => return Ok(connection),
+            Err(Error::HandshakeTimeout) => return Err(Error::HandshakeTimeout.into()),
+            Err(err) => {
+                crate::log::debug!("HTTP/3 connection to {addr} failed: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| {
+            Error::Connect(io::Error::new(io::ErrorKind::NotFound, "no addresses found").into())
+        })
+        .into())
+}
+
+/// Connects to a single address, giving up at `deadline`
+async fn connect_to(
+    endpoints: &Endpoints,
+    addr: SocketAddr,
+    quic_config: &quinn::ClientConfig,
+    server_name: &str,
+    deadline: Option<Instant>,
+) -> Result<Http3Connection, Error> {
+    let endpoint = endpoints
+        .get(addr)
+        .map_err(|e| Error::Connect(e.into()))?;
+    let connecting = endpoint
+        .connect_with(quic_config.clone(), addr, server_name)
+        .map_err(|e| Error::Quic(e.into()))?;
+
+    let handshake = async {
+        let connection = connecting.await?;
+        let (driver, send_request) =
+            h3::client::new(h3_quinn::Connection::new(connection.clone())).await?;
+        Ok::<_, BoxError>((connection, driver, send_request))
+    };
+    let (connection, mut driver, send_request) = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, handshake)
+            .await
+            .map_err(|_| Error::HandshakeTimeout)?,
+        None => handshake.await,
+    }
+    .map_err(Error::Quic)?;
+
+    // The driver handles the control streams until the connection closes
+    tokio::spawn(async move {
+        let err = driver.wait_idle().await;
+        crate::log::debug!("HTTP/3 connection closed: {err}");
+    });
+
+    Ok(Http3Connection {
+        connection,
+        send_request,
+    })
+}
+
+impl fmt::Debug for Http3Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Http3Connector")
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("config_resolver", &self.config_resolver.is_some())
+            .field("client_cert_resolver", &self.client_cert_resolver)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An HTTP/3 connection made by an [`Http3Connector`]
+///
+/// The request handle is the `h3` crate's, which is not stable yet, see
+/// [the stability notes](Http3Connector#stability).
+///
+/// The connection is closed once it has been dropped, along with all the
+/// clones of its [`SendRequest`] handle.
+pub struct Http3Connection {
+    connection: quinn::Connection,
+    send_request: SendRequest<OpenStreams, Bytes>,
+}
+
+impl Http3Connection {
+    /// The handle for sending requests over the connection
+    ///
+    /// The handle can be cloned to send requests concurrently.
+    pub fn send_request(&mut self) -> &mut SendRequest<OpenStreams, Bytes> {
+        &mut self.send_request
+    }
+
+    /// Consumes the connection, returning the handle for sending requests
+    pub fn into_send_request(self) -> SendRequest<OpenStreams, Bytes> {
+        self.send_request
+    }
+
+    /// The underlying QUIC connection
+    ///
+    /// This gives access to the peer's address, its certificates and the
+    /// connection statistics.
+    pub fn quic_connection(&self) -> &quinn::Connection {
+        &self.connection
+    }
+}
+
+impl fmt::Debug for Http3Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Http3Connection")
+            .field("remote_address", &self.connection.remote_address())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Looks up the addresses of the destination, on port 443 by default
+async fn resolve(dst: &Uri) -> io::Result<Vec<SocketAddr>> {
+    let host = dst.host().unwrap_or_default();
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    Ok(
+        tokio::net::lookup_host((host, dst.port_u16().unwrap_or(443)))
+            .await?
+            .collect(),
+    )
+}
+
+/// The client endpoints, bound on first use for each address family
+#[derive(Default)]
+struct Endpoints {
+    v4: Mutex<Option<Endpoint>>,
+    v6: Mutex<Option<Endpoint>>,
+}
+
+impl Endpoints {
+    fn get(&self, remote: SocketAddr) -> io::Result<Endpoint> {
+        let (endpoint, local) = match remote {
+            SocketAddr::V4(_) => (&self.v4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+            SocketAddr::V6(_) => (&self.v6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))),
+        };
+        let mut endpoint = endpoint
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match &*endpoint {
+            Some(endpoint) => Ok(endpoint.clone()),
+            None => Ok(endpoint
+                .insert(Endpoint::client(local)?)
+                .clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::{Buf, Bytes};
+    use http::header::ALT_SVC;
+    use http::{HeaderMap, HeaderValue, Request, Response, StatusCode, Uri, Version};
+    use quinn::crypto::rustls::{HandshakeData, QuicClientConfig, QuicServerConfig};
+    use quinn::Endpoint;
+    use rustls::pki_types::ServerName;
+    use tower_service::Service;
+
+    use super::Http3Connector;
+    use crate::test_util::{server_config, trusting_config};
+    use crate::{
+        AltSvcCache, BuilderError, Error, FixedServerNameResolver, HttpsConnectorBuilder, Proxy,
+    };
+
+    #[tokio::test]
+    async fn request() {
+        let addr = h3_server().await;
+        let mut connector = connector(trusting_config());
+
+        let uri = Uri::try_from(format!("https://127.0.0.1:{}/hello", addr.port())).unwrap();
+        let mut connection = connector
+            .call(uri.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection
+                .quic_connection()
+                .remote_address(),
+            addr
+        );
+        let handshake = connection
+            .quic_connection()
+            .handshake_data()
+            .unwrap()
+            .downcast::<HandshakeData>()
+            .unwrap();
+        assert_eq!(handshake.protocol.as_deref(), Some(&b"h3"[..]));
+
+        let mut stream = connection
+            .send_request()
+            .send_request(Request::get(uri).body(()).unwrap())
+            .await
+            .unwrap();
+        stream.finish().await.unwrap();
+        let response = stream.recv_response().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_3);
+
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.recv_data().await.unwrap() {
+            body.extend_from_slice(chunk.chunk());
+        }
+        assert_eq!(body, b"/hello");
+    }
+
+    #[tokio::test]
+    async fn tries_each_address() {
+        let addr = h3_server().await;
+        // Rejected by quinn before sending anything
+        let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port()));
+
+        let connection = super::connect(
+            &super::Endpoints::default(),
+            vec![unspecified, addr],
+            &quic_config(),
+            "localhost",
+            Some(Duration::from_secs(5)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            connection
+                .quic_connection()
+                .remote_address(),
+            addr
+        );
+    }
+
+    #[tokio::test]
+    async fn one_deadline_for_all_addresses() {
+        // Never answer, so the handshakes with them time out
+        let silent = [(); 3].map(|_| std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap());
+        let addrs = silent
+            .iter()
+            .map(|socket| socket.local_addr().unwrap())
+            .collect();
+
+        let timeout = Duration::from_millis(200);
+        let start = tokio::time::Instant::now();
+        let err = super::connect(
+            &super::Endpoints::default(),
+            addrs,
+            &quic_config(),
+            "localhost",
+            Some(timeout),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::HandshakeTimeout)
+        ));
+        assert!(start.elapsed() < timeout * 2);
+    }
+
+    #[tokio::test]
+    async fn alt_svc() {
+        let addr = h3_server().await;
+        let connector = connector(trusting_config());
+        let cache = AltSvcCache::new();
+        let origin = Uri::from_static("https://localhost/");
+        assert!(connector
+            .call_alt_svc(origin.clone(), &cache)
+            .is_none());
+
+        cache.record(&origin, &alt_svc_header(addr));
+        let connection = connector
+            .call_alt_svc(origin, &cache)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(
+            connection
+                .quic_connection()
+                .remote_address(),
+            addr
+        );
+    }
+
+    #[cfg(feature = "http1")]
+    #[tokio::test]
+    async fn alt_svc_falls_back_to_tcp() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        use crate::test_util::sample_acceptor;
+        use crate::{AltSvcConnector, MaybeHttpsStream};
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let origin = Uri::try_from(format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        let acceptor = sample_acceptor(Vec::new());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut tls) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let _ = tls.read(&mut [0; 1]).await;
+                });
+            }
+        });
+
+        // Never answers, so the QUIC handshake with it times out
+        let silent = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let cache = AltSvcCache::new();
+        cache.record(&origin, &alt_svc_header(silent.local_addr().unwrap()));
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_handshake_timeout(Duration::from_millis(200))
+            .enable_http3()
+            .build();
+        let err = connector
+            .call_alt_svc(origin.clone(), &cache)
+            .unwrap()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::HandshakeTimeout)
+        ));
+        assert!(cache.alternatives(&origin).is_empty());
+        assert!(connector
+            .call_alt_svc(origin.clone(), &cache)
+            .is_none());
+
+        let https = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .enable_http1()
+            .build();
+        let stream = AltSvcConnector::new(https, cache)
+            .call(origin)
+            .await
+            .unwrap();
+        assert!(matches!(stream, MaybeHttpsStream::Https(_)));
+    }
+
+    #[tokio::test]
+    async fn untrusted_server() {
+        let addr = h3_server().await;
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let mut connector = connector(config);
+
+        let uri = Uri::try_from(format!("https://127.0.0.1:{}", addr.port())).unwrap();
+        let err = connector.call(uri).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Quic(_))));
+    }
+
+    #[tokio::test]
+    async fn unsupported_scheme() {
+        let mut connector = connector(trusting_config());
+        let err = connector
+            .call(Uri::from_static("http://localhost"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnsupportedScheme(_))
+        ));
+    }
+
+    #[test]
+    fn unsupported_settings() {
+        let err = HttpsConnectorBuilder::new()
+            .with_tls_config(trusting_config())
+            .https_only()
+            .with_proxy(Proxy::new(Uri::from_static("http://proxy.local:3128")))
+            .with_early_data()
+            .enable_http3()
+            .try_build()
+            .unwrap_err();
+        assert!(matches!(
+            err.problems(),
+            [
+                BuilderError::UnsupportedWithHttp3("a proxy"),
+                BuilderError::UnsupportedWithHttp3("early data"),
+            ]
+        ));
+    }
+
+    fn connector(config: rustls::ClientConfig) -> Http3Connector {
+        HttpsConnectorBuilder::new()
+            .with_tls_config(config)
+            .https_only()
+            .with_server_name_resolver(FixedServerNameResolver::new(
+                ServerName::try_from("localhost").unwrap(),
+            ))
+            .enable_http3()
+            .build()
+    }
+
+    fn quic_config() -> quinn::ClientConfig {
+        let mut config = trusting_config();
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(config).unwrap()))
+    }
+
+    /// An `Alt-Svc` header advertising an `h3` alternative on `addr`
+    fn alt_svc_header(addr: SocketAddr) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ALT_SVC,
+            HeaderValue::try_from(format!("h3=\"{addr}\"")).unwrap(),
+        );
+        headers
+    }
+
+    /// Answers every request with its path
+    async fn h3_server() -> SocketAddr {
+        let mut config = server_config();
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        let config = QuicServerConfig::try_from(config).unwrap();
+        let endpoint = Endpoint::server(
+            quinn::ServerConfig::with_crypto(Arc::new(config)),
+            (Ipv4Addr::LOCALHOST, 0).into(),
+        )
+        .unwrap();
+        let addr = endpoint.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    let Ok(connection) = incoming.await else {
+                        return;
+                    };
+                    let mut connection = h3::server::Connection::<_, Bytes>::new(
+                        h3_quinn::Connection::new(connection),
+                    )
+                    .await
+                    .unwrap();
+                    while let Ok(Some(resolver)) = connection.accept().await {
+                        let (request, mut stream) = resolver
+                            .resolve_request()
+                            .await
+                            .unwrap();
+                        stream
+                            .send_response(Response::new(()))
+                            .await
+                            .unwrap();
+                        stream
+                            .send_data(Bytes::from(request.uri().path().to_owned()))
+                            .await
+                            .unwrap();
+                        stream.finish().await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+}
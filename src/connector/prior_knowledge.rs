@@ -0,0 +1,66 @@
+use http::Uri;
+
+use super::router::Host;
+
+/// The plain `http://` destinations an [`HttpsConnector`](crate::HttpsConnector)
+/// speaks HTTP/2 to with prior knowledge (h2c)
+///
+/// Connections to these destinations are returned as
+/// [`MaybeHttpsStream::H2c`](crate::MaybeHttpsStream::H2c), which hyper uses
+/// for HTTP/2 without any upgrade, so the servers must accept the HTTP/2
+/// connection preface right away. `https://` destinations are not affected
+/// and keep negotiating the HTTP version with ALPN.
+///
+/// Hosts are either exact names or wildcard patterns like `*.example.com`,
+/// as for [`ClientConfigRouter`](crate::ClientConfigRouter), and match on
+/// any port.
+///
+/// # Examples
+///
+/// ```
+/// use hyper_rustls::Http2PriorKnowledge;
+///
+/// let prior_knowledge = Http2PriorKnowledge::hosts(["localhost", "*.svc.cluster.local"]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Http2PriorKnowledge {
+    hosts: Option<Vec<Host>>,
+}
+
+impl Http2PriorKnowledge {
+    /// Speaks HTTP/2 with prior knowledge to every plain destination
+    pub fn all() -> Self {
+        Self { hosts: None }
+    }
+
+    /// Speaks HTTP/2 with prior knowledge to the plain destinations matching
+    /// one of `hosts`
+    ///
+    /// Hosts are compared case-insensitively, and may be wildcard patterns
+    /// like `*.example.com`.
+    pub fn hosts<I>(hosts: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Self {
+            hosts: Some(
+                hosts
+                    .into_iter()
+                    .map(|host| Host::parse(host.as_ref()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Whether HTTP/2 is spoken with prior knowledge to `uri`
+    pub(crate) fn applies(&self, uri: &Uri) -> bool {
+        let Some(hosts) = &self.hosts else {
+            return true;
+        };
+        let host = Host::of(uri);
+        hosts
+            .iter()
+            .any(|pattern| pattern.matches(&host).is_some())
+    }
+}
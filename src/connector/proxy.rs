@@ -0,0 +1,1067 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fmt, io};
+
+use http::{HeaderValue, StatusCode, Uri};
+use hyper::rt;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::TlsConnector;
+
+use super::{handshake, sent_server_name};
+use crate::stream::HttpsStream;
+use crate::Error;
+
+mod socks;
+
+/// A proxy that connections are tunnelled through
+///
+/// When configured on an [`HttpsConnector`](crate::HttpsConnector), the
+/// connector first connects to the proxy, asks it to open a tunnel to the
+/// destination, and then runs the TLS handshake with the destination over
+/// that tunnel. The server name used for the handshake is still derived from
+/// the destination URI, not from the proxy.
+///
+/// The protocol spoken with the proxy is selected by the scheme of its URI:
+///
+/// - `http` or `https`: an HTTP `CONNECT` request (`https` proxies must be
+///   given a TLS configuration with [`Proxy::with_tls()`])
+/// - `socks5`: SOCKS5, with destination host names resolved locally
+/// - `socks5h`: SOCKS5, with destination host names resolved by the proxy
+///
+/// The connection to the proxy itself may also be protected with TLS (see
+/// [`Proxy::with_tls()`]), in which case the TLS session with the destination
+/// runs inside the TLS session with the proxy.
+#[derive(Clone)]
+pub struct Proxy {
+    uri: Uri,
+    protocol: Protocol,
+    credentials: Option<(String, String)>,
+    authorization: Option<HeaderValue>,
+    no_proxy: NoProxy,
+    tls: Option<(Arc<ClientConfig>, ServerName<'static>)>,
+    timeout: Option<Duration>,
+}
+
+impl Proxy {
+    /// Creates a proxy reachable at `uri`, for example `http://proxy.example:3128`
+    ///
+    /// SOCKS5 proxies default to port 1080 if `uri` has no port.
+    pub fn new(uri: Uri) -> Self {
+        let protocol = match uri.scheme_str() {
+            Some("socks5") => Protocol::Socks5 { remote_dns: false },
+            Some("socks5h") => Protocol::Socks5 { remote_dns: true },
+            _ => Protocol::Http,
+        };
+
+        Self {
+            uri,
+            protocol,
+            credentials: None,
+            authorization: None,
+            no_proxy: NoProxy::default(),
+            tls: None,
+            timeout: None,
+        }
+    }
+
+    /// Connects to the proxy over TLS
+    ///
+    /// The proxy's certificate is verified against `server_name` using
+    /// `config`, which is independent of the configuration used for the
+    /// destination. Any ALPN protocols in `config` are sent as-is.
+    ///
+    /// This is required for proxies with an `https` URI: connecting through
+    /// them fails otherwise, rather than talking to the proxy in plaintext.
+    pub fn with_tls(
+        mut self,
+        config: impl Into<Arc<ClientConfig>>,
+        server_name: ServerName<'static>,
+    ) -> Self {
+        self.tls = Some((config.into(), server_name));
+        self
+    }
+
+    /// Authenticates to the proxy with the given username and password
+    ///
+    /// For HTTP proxies, this sends a `Proxy-Authorization` header using
+    /// the `Basic` scheme. For SOCKS5 proxies, this offers username/password
+    /// authentication.
+    pub fn with_basic_auth(mut self, username: &str, password: &str) -> Self {
+        let credentials = base64(format!("{username}:{password}").as_bytes());
+        let mut value = HeaderValue::try_from(format!("Basic {credentials}"))
+            .expect("base64 is always a valid header value");
+        value.set_sensitive(true);
+        self.credentials = Some((username.to_owned(), password.to_owned()));
+        self.with_authorization(value)
+    }
+
+    /// Sends the given value as the `Proxy-Authorization` header
+    ///
+    /// This has no effect for SOCKS5 proxies.
+    pub fn with_authorization(mut self, value: HeaderValue) -> Self {
+        self.authorization = Some(value);
+        self
+    }
+
+    /// Limits the time the proxy may take to open the tunnel
+    ///
+    /// This covers the `CONNECT` request or the SOCKS5 negotiation, but not
+    /// connecting to the proxy or the TLS handshake with it. Without a limit
+    /// set here, the handshake timeout of the connector applies, if any.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connects directly to destinations matched by `no_proxy`
+    pub fn with_no_proxy(mut self, no_proxy: NoProxy) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    /// The URI of the proxy itself
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// The URI the underlying connector is asked to connect to
+    pub(crate) fn connect_uri(&self) -> Uri {
+        let Protocol::Socks5 { .. } = self.protocol else {
+            return self.uri.clone();
+        };
+
+        // Present SOCKS proxies as plain addresses to the underlying connector,
+        // which does not know their schemes or default port
+        let host = self.uri.host().unwrap_or_default();
+        let port = self.uri.port_u16().unwrap_or(1080);
+        Uri::try_from(format!("http://{host}:{port}")).unwrap_or_else(|_| self.uri.clone())
+    }
+
+    /// Returns whether connections to `dst` should go through this proxy
+    pub fn intercepts(&self, dst: &Uri) -> bool {
+        match dst.host() {
+            Some(host) => !self.no_proxy.matches(host),
+            None => true,
+        }
+    }
+
+    /// Opens a tunnel to `dst` over `stream`, which must be connected to the proxy
+    ///
+    /// If the proxy uses TLS, the handshake with the proxy is performed first.
+    pub(crate) async fn open<T>(
+        &self,
+        stream: T,
+        dst: &Uri,
+        handshake_timeout: Option<Duration>,
+    ) -> Result<Tunnel<T>, Error>
+    where
+        T: rt::Read + rt::Write + Unpin,
+    {
+        let timeout = self.timeout.or(handshake_timeout);
+        let Some((config, server_name)) = &self.tls else {
+            if self.uri.scheme() == Some(&http::uri::Scheme::HTTPS) {
+                return Err(Error::Tunnel(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "https proxy configured without TLS, see Proxy::with_tls()",
+                )));
+            }
+
+            let mut stream = TokioIo::new(stream);
+            self.tunnel(&mut stream, dst, timeout)
+                .await?;
+            return Ok(Tunnel::Plain(stream.into_inner()));
+        };
+
+        let tls = handshake(
+            TlsConnector::from(config.clone()).connect(server_name.clone(), TokioIo::new(stream)),
+            handshake_timeout,
+        )
+        .await?;
+        let mut stream = TokioIo::new(HttpsStream::new(tls, sent_server_name(config, server_name)));
+        self.tunnel(&mut stream, dst, timeout)
+            .await?;
+        Ok(Tunnel::Tls(stream.into_inner()))
+    }
+
+    async fn tunnel<S>(
+        &self,
+        stream: &mut S,
+        dst: &Uri,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = dst.host().unwrap_or_default();
+        let port = match dst.port_u16() {
+            Some(port) => port,
+            None if dst.scheme() == Some(&http::uri::Scheme::HTTP) => 80,
+            None => 443,
+        };
+
+        let tunnel = async {
+            match self.protocol {
+                Protocol::Http => {
+                    self.http_connect(stream, host, port)
+                        .await
+                }
+                Protocol::Socks5 { remote_dns } => {
+                    self.socks5_connect(stream, host, port, remote_dns)
+                        .await
+                }
+            }
+        };
+
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, tunnel)
+                .await
+                .map_err(|_| {
+                    Error::Tunnel(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "proxy did not open the tunnel in time",
+                    ))
+                })?,
+            None => tunnel.await,
+        }
+    }
+
+    async fn socks5_connect<S>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        remote_dns: bool,
+    ) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let target = match host.parse::<IpAddr>() {
+            Ok(ip) => socks::Target::Addr(SocketAddr::new(ip, port)),
+            Err(_) if remote_dns => socks::Target::Domain(host, port),
+            Err(_) => socks::Target::Addr(
+                tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(Error::Tunnel)?
+                    .next()
+                    .ok_or_else(|| {
+                        Error::Tunnel(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no addresses found for {host}"),
+                        ))
+                    })?,
+            ),
+        };
+
+        let credentials = self
+            .credentials
+            .as_ref()
+            .map(|(username, password)| (username.as_str(), password.as_str()));
+        socks::connect(stream, target, credentials)
+            .await
+            .map_err(Error::Tunnel)
+    }
+
+    async fn http_connect<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some(authorization) = &self.authorization {
+            let value = authorization
+                .to_str()
+                .map_err(|e| Error::Tunnel(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+            request.push_str(&format!("Proxy-Authorization: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(Error::Tunnel)?;
+        stream
+            .flush()
+            .await
+            .map_err(Error::Tunnel)?;
+
+        let mut buf = Vec::with_capacity(256);
+        while !buf.ends_with(HEAD_END) {
+            if buf.len() >= MAX_RESPONSE_HEAD {
+                return Err(Error::Tunnel(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "proxy response headers too long",
+                )));
+            }
+
+            // Never read past the end of the head: a body (of an error
+            // response) is not needed, and after a successful response any
+            // further bytes belong to the tunnelled connection.
+            let partial = (1..HEAD_END.len())
+                .rev()
+                .find(|&n| buf.ends_with(&HEAD_END[..n]))
+                .unwrap_or(0);
+            let mut chunk = [0; HEAD_END.len()];
+            let n = stream
+                .read(&mut chunk[..HEAD_END.len() - partial])
+                .await
+                .map_err(Error::Tunnel)?;
+            if n == 0 {
+                return Err(Error::Tunnel(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "proxy closed the connection during CONNECT",
+                )));
+            }
+
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        match parse_status(&buf) {
+            Some(status) if status.is_success() => Ok(()),
+            Some(status) => Err(Error::ProxyRejected(status)),
+            None => Err(Error::Tunnel(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed proxy response",
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("uri", &self.uri)
+            .field("protocol", &self.protocol)
+            .field("no_proxy", &self.no_proxy)
+            .field("tls", &self.tls.is_some())
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Protocol {
+    Http,
+    Socks5 { remote_dns: bool },
+}
+
+/// A tunnel to the destination, opened through a proxy
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Tunnel<T> {
+    Plain(T),
+    Tls(HttpsStream<T>),
+}
+
+/// Destinations that should bypass the proxy
+///
+/// This follows the common `NO_PROXY` conventions: a comma-separated list
+/// of host names, domain suffixes and IP addresses, where `*` matches every
+/// host. A domain such as `example.com` (or `.example.com`) matches the domain
+/// itself as well as all of its subdomains.
+#[derive(Clone, Debug, Default)]
+pub struct NoProxy {
+    entries: Vec<String>,
+}
+
+impl NoProxy {
+    /// Parses a comma-separated exclusion list
+    pub fn new(list: &str) -> Self {
+        Self {
+            entries: list
+                .split(',')
+                .map(|entry| {
+                    entry
+                        .trim()
+                        .trim_start_matches('.')
+                        .to_ascii_lowercase()
+                })
+                .filter(|entry| !entry.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Reads the exclusion list from the `NO_PROXY` (or `no_proxy`) environment variable
+    pub fn from_env() -> Self {
+        env::var("NO_PROXY")
+            .or_else(|_| env::var("no_proxy"))
+            .map(|list| Self::new(&list))
+            .unwrap_or_default()
+    }
+
+    /// Returns whether `host` is excluded from proxying
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host)
+            .to_ascii_lowercase();
+        let ip = host.parse::<IpAddr>().ok();
+
+        self.entries.iter().any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+
+            if let Some(ip) = ip {
+                return entry
+                    .parse::<IpAddr>()
+                    .is_ok_and(|entry| entry == ip);
+            }
+
+            match host.strip_suffix(entry.as_str()) {
+                Some("") => true,
+                Some(prefix) => prefix.ends_with('.'),
+                None => false,
+            }
+        })
+    }
+}
+
+fn parse_status(head: &[u8]) -> Option<StatusCode> {
+    let line = head.split(|&b| b == b'\r').next()?;
+    let mut parts = line.split(|&b| b == b' ');
+    if !parts.next()?.starts_with(b"HTTP/1.") {
+        return None;
+    }
+    StatusCode::from_bytes(parts.next()?).ok()
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let n = match *chunk {
+            [a, b, c] => u32::from_be_bytes([0, a, b, c]),
+            [a, b] => u32::from_be_bytes([0, a, b, 0]),
+            [a] => u32::from_be_bytes([0, a, 0, 0]),
+            _ => unreachable!(),
+        };
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+const HEAD_END: &[u8] = b"\r\n\r\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_proxy_matches() {
+        let no_proxy = NoProxy::new("example.com, .internal,10.0.0.1, [::1]");
+        assert!(no_proxy.matches("example.com"));
+        assert!(no_proxy.matches("api.example.com"));
+        assert!(no_proxy.matches("API.Example.COM"));
+        assert!(!no_proxy.matches("badexample.com"));
+        assert!(no_proxy.matches("svc.internal"));
+        assert!(no_proxy.matches("internal"));
+        assert!(no_proxy.matches("10.0.0.1"));
+        assert!(!no_proxy.matches("10.0.0.2"));
+        assert!(!no_proxy.matches("hyper.rs"));
+    }
+
+    #[test]
+    fn no_proxy_wildcard() {
+        assert!(NoProxy::new("*").matches("hyper.rs"));
+        assert!(!NoProxy::new("").matches("hyper.rs"));
+    }
+
+    #[test]
+    fn parses_status() {
+        assert_eq!(
+            parse_status(b"HTTP/1.1 200 Connection established\r\n\r\n"),
+            Some(StatusCode::OK)
+        );
+        assert_eq!(
+            parse_status(b"HTTP/1.0 407 Proxy Authentication Required\r\n\r\n"),
+            Some(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+        );
+        assert_eq!(parse_status(b"SSH-2.0-OpenSSH\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(
+            base64(b"Aladdin:open sesame"),
+            "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    /// Connections through proxies served on the loopback address
+    #[cfg(all(feature = "http1", any(feature = "ring", feature = "aws-lc-rs")))]
+    mod tunnels {
+        use std::net::{Ipv4Addr, SocketAddr};
+
+        use hyper_util::client::legacy::connect::Connection;
+        use tokio::net::{TcpListener, TcpStream};
+        use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+        use tower_service::Service;
+
+        use super::*;
+        use crate::test_util::{
+            connected_tls_info, sample_acceptor, silent_server, tls_server, trusting_config,
+        };
+        use crate::{HttpsConnectorBuilder, MaybeHttpsStream, TlsInfo};
+
+        #[tokio::test]
+        async fn proxy_tunnel() {
+            let addr = tls_server(Vec::new()).await;
+            let (proxy, mut requested) = proxy_server(Protocol::Connect(None)).await;
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_or_http()
+                .with_proxy(Proxy::new(
+                    format!("http://{proxy}")
+                        .parse()
+                        .unwrap(),
+                ))
+                .enable_http1()
+                .build();
+
+            let stream = service
+                .call(
+                    format!("https://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                requested.recv().await.unwrap(),
+                format!("localhost:{}", addr.port())
+            );
+            let info = connected_tls_info(&stream);
+            assert_eq!(
+                info.server_name(),
+                Some(&ServerName::try_from("localhost").unwrap())
+            );
+
+            let stream = service
+                .call(
+                    format!("http://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(matches!(stream, MaybeHttpsStream::Http(_)));
+        }
+
+        #[tokio::test]
+        async fn proxy_authorization() {
+            let addr = tls_server(Vec::new()).await;
+            let (proxy, _requested) =
+                proxy_server(Protocol::Connect(Some("Basic dXNlcjpwYXNz"))).await;
+            let dst = Uri::try_from(format!("https://localhost:{}", addr.port())).unwrap();
+            let proxy = Proxy::new(
+                format!("http://{proxy}")
+                    .parse()
+                    .unwrap(),
+            );
+
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(proxy.clone())
+                .enable_http1()
+                .build();
+            let err = service
+                .call(dst.clone())
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::ProxyRejected(
+                    StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                ))
+            ));
+
+            service.set_proxy(Some(proxy.with_basic_auth("user", "pass")));
+            service.call(dst).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn proxy_rejection_with_body() {
+            let (proxy, _requested) =
+                proxy_server(Protocol::Connect(Some("Basic dXNlcjpwYXNz"))).await;
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(Proxy::new(
+                    format!("http://{proxy}")
+                        .parse()
+                        .unwrap(),
+                ))
+                .enable_http1()
+                .build();
+
+            let call = service.call("https://localhost".parse().unwrap());
+            let err = tokio::time::timeout(Duration::from_secs(5), call)
+                .await
+                .expect("reading the proxy response stalled")
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::ProxyRejected(
+                    StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                ))
+            ));
+        }
+
+        #[tokio::test]
+        async fn proxy_timeout() {
+            let (proxy, _server) = silent_server().await;
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(
+                    Proxy::new(
+                        format!("http://{proxy}")
+                            .parse()
+                            .unwrap(),
+                    )
+                    .with_timeout(Duration::from_millis(50)),
+                )
+                .enable_http1()
+                .build();
+
+            let err = service
+                .call("https://localhost".parse().unwrap())
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Tunnel(err)) if err.kind() == io::ErrorKind::TimedOut
+            ));
+        }
+
+        #[tokio::test]
+        async fn https_proxy_requires_tls() {
+            let (proxy, _requested) = proxy_server(Protocol::Connect(None)).await;
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(Proxy::new(
+                    format!("https://{proxy}")
+                        .parse()
+                        .unwrap(),
+                ))
+                .enable_http1()
+                .build();
+
+            let err = service
+                .call("https://localhost".parse().unwrap())
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Tunnel(err)) if err.kind() == io::ErrorKind::InvalidInput
+            ));
+        }
+
+        #[tokio::test]
+        async fn no_proxy() {
+            let addr = tls_server(Vec::new()).await;
+            // Nothing listens on this port, so going through the proxy would fail
+            let unused = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let proxy = Proxy::new(
+                format!("http://{unused}")
+                    .parse()
+                    .unwrap(),
+            )
+            .with_no_proxy(NoProxy::new("example.com,localhost"));
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(proxy)
+                .enable_http1()
+                .build();
+
+            service
+                .call(
+                    format!("https://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn https_proxy_tunnel() {
+            let addr = tls_server(Vec::new()).await;
+            let (proxy, _requested) = proxy_server(Protocol::ConnectOverTls).await;
+            let proxy = Proxy::new(
+                format!("https://{proxy}")
+                    .parse()
+                    .unwrap(),
+            )
+            .with_tls(
+                trusting_config(),
+                ServerName::try_from("localhost").unwrap(),
+            );
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_or_http()
+                .with_proxy(proxy)
+                .enable_http1()
+                .build();
+
+            let stream = service
+                .call(
+                    format!("https://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let MaybeHttpsStream::Https(https) = &stream else {
+                panic!("expected a TLS stream");
+            };
+            assert!(https.proxy().is_some());
+            assert_eq!(
+                connected_tls_info(&stream).server_name(),
+                Some(&ServerName::try_from("localhost").unwrap())
+            );
+
+            // The destination's greeting arrives through both TLS layers
+            let mut stream = TokioIo::new(stream);
+            let mut greeting = [0; 2];
+            stream
+                .read_exact(&mut greeting)
+                .await
+                .unwrap();
+            assert_eq!(&greeting, b"ok");
+
+            let stream = service
+                .call(
+                    format!("http://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(matches!(stream, MaybeHttpsStream::HttpOverTls(_)));
+            let mut extensions = http::Extensions::new();
+            stream
+                .connected()
+                .get_extras(&mut extensions);
+            assert!(extensions.get::<TlsInfo>().is_none());
+        }
+
+        #[tokio::test]
+        async fn socks5_remote_dns() {
+            let addr = tls_server(Vec::new()).await;
+            let (proxy, mut requested) =
+                proxy_server(Protocol::Socks5(Some(("user", "pass")))).await;
+            let proxy = Proxy::new(
+                format!("socks5h://{proxy}")
+                    .parse()
+                    .unwrap(),
+            )
+            .with_basic_auth("user", "pass");
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(proxy)
+                .enable_http1()
+                .build();
+
+            let stream = service
+                .call(
+                    format!("https://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                requested.recv().await.unwrap(),
+                format!("localhost:{}", addr.port())
+            );
+            assert_eq!(
+                connected_tls_info(&stream).server_name(),
+                Some(&ServerName::try_from("localhost").unwrap())
+            );
+        }
+
+        #[tokio::test]
+        async fn socks5_local_dns() {
+            let addr = tls_server(Vec::new()).await;
+            let (proxy, mut requested) = proxy_server(Protocol::Socks5(None)).await;
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(Proxy::new(
+                    format!("socks5://{proxy}")
+                        .parse()
+                        .unwrap(),
+                ))
+                .enable_http1()
+                .build();
+
+            let stream = service
+                .call(
+                    format!("https://localhost:{}", addr.port())
+                        .parse()
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let target = requested.recv().await.unwrap();
+            assert!(target.parse::<SocketAddr>().is_ok(), "{target}");
+            assert_eq!(
+                connected_tls_info(&stream).server_name(),
+                Some(&ServerName::try_from("localhost").unwrap())
+            );
+        }
+
+        #[tokio::test]
+        async fn socks5_rejected_credentials() {
+            let (proxy, _requested) = proxy_server(Protocol::Socks5(Some(("user", "pass")))).await;
+            let proxy = Proxy::new(
+                format!("socks5h://{proxy}")
+                    .parse()
+                    .unwrap(),
+            )
+            .with_basic_auth("user", "nope");
+            let mut service = HttpsConnectorBuilder::new()
+                .with_tls_config(trusting_config())
+                .https_only()
+                .with_proxy(proxy)
+                .enable_http1()
+                .build();
+
+            let err = service
+                .call(Uri::from_static("https://localhost"))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Tunnel(err)) if err.kind() == io::ErrorKind::PermissionDenied
+            ));
+        }
+
+        /// The protocols spoken by [`proxy_server()`]
+        #[derive(Clone, Copy)]
+        enum Protocol {
+            /// HTTP `CONNECT`, optionally requiring a `Proxy-Authorization` header
+            Connect(Option<&'static str>),
+            /// HTTP `CONNECT`, with clients connecting to the proxy over TLS
+            ConnectOverTls,
+            /// SOCKS5, optionally requiring a username and password
+            Socks5(Option<(&'static str, &'static str)>),
+        }
+
+        /// A minimal proxy speaking `protocol`
+        ///
+        /// Every requested destination is reported through the returned channel,
+        /// but tunnels always go to the loopback address, where the test servers
+        /// listen.
+        async fn proxy_server(protocol: Protocol) -> (SocketAddr, UnboundedReceiver<String>) {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let acceptor = sample_acceptor(Vec::new());
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let (acceptor, tx) = (acceptor.clone(), tx.clone());
+                    tokio::spawn(async move {
+                        match protocol {
+                            Protocol::Connect(authorization) => {
+                                serve_connect(stream, authorization, tx).await
+                            }
+                            Protocol::ConnectOverTls => {
+                                if let Ok(stream) = acceptor.accept(stream).await {
+                                    serve_connect(stream, None, tx).await;
+                                }
+                            }
+                            Protocol::Socks5(credentials) => {
+                                serve_socks5(stream, credentials, tx).await
+                            }
+                        }
+                    });
+                }
+            });
+            (addr, rx)
+        }
+
+        async fn serve_connect<S>(
+            mut stream: S,
+            authorization: Option<&'static str>,
+            requested: UnboundedSender<String>,
+        ) where
+            S: AsyncRead + AsyncWrite + Unpin,
+        {
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                if stream
+                    .read(&mut byte)
+                    .await
+                    .unwrap_or(0)
+                    == 0
+                {
+                    return;
+                }
+                head.push(byte[0]);
+            }
+
+            let head = String::from_utf8(head).unwrap();
+            let target = head
+                .strip_prefix("CONNECT ")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap()
+                .to_owned();
+            if let Some(expected) = authorization {
+                if !head.contains(&format!("Proxy-Authorization: {expected}\r\n")) {
+                    // Send the head and body at once and keep the connection open,
+                    // as a proxy supporting keep-alive would
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                              Proxy-Authenticate: Basic\r\nContent-Length: 12\r\n\r\n\
+                              unauthorized",
+                        )
+                        .await;
+                    let _ = stream.read(&mut [0; 1]).await;
+                    return;
+                }
+            }
+
+            let port = target
+                .rsplit_once(':')
+                .and_then(|(_, port)| port.parse().ok())
+                .unwrap();
+            let _ = requested.send(target);
+            let Ok(mut upstream) = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await else {
+                let _ = stream
+                    .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                    .await;
+                return;
+            };
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        }
+
+        async fn serve_socks5(
+            mut stream: TcpStream,
+            credentials: Option<(&'static str, &'static str)>,
+            requested: UnboundedSender<String>,
+        ) {
+            let mut greeting = [0; 2];
+            stream
+                .read_exact(&mut greeting)
+                .await
+                .unwrap();
+            let mut methods = vec![0; greeting[1] as usize];
+            stream
+                .read_exact(&mut methods)
+                .await
+                .unwrap();
+
+            if let Some((username, password)) = credentials {
+                if !methods.contains(&2) {
+                    let _ = stream.write_all(&[5, 0xff]).await;
+                    return;
+                }
+                stream.write_all(&[5, 2]).await.unwrap();
+                let mut fields = Vec::new();
+                stream.read_u8().await.unwrap();
+                for _ in 0..2 {
+                    let len = stream.read_u8().await.unwrap();
+                    let mut field = vec![0; len as usize];
+                    stream
+                        .read_exact(&mut field)
+                        .await
+                        .unwrap();
+                    fields.push(String::from_utf8(field).unwrap());
+                }
+                if fields != [username, password] {
+                    let _ = stream.write_all(&[1, 1]).await;
+                    return;
+                }
+                stream.write_all(&[1, 0]).await.unwrap();
+            } else {
+                stream.write_all(&[5, 0]).await.unwrap();
+            }
+
+            let mut request = [0; 4];
+            stream
+                .read_exact(&mut request)
+                .await
+                .unwrap();
+            let host = match request[3] {
+                1 => {
+                    let mut ip = [0; 4];
+                    stream
+                        .read_exact(&mut ip)
+                        .await
+                        .unwrap();
+                    Ipv4Addr::from(ip).to_string()
+                }
+                3 => {
+                    let len = stream.read_u8().await.unwrap();
+                    let mut name = vec![0; len as usize];
+                    stream
+                        .read_exact(&mut name)
+                        .await
+                        .unwrap();
+                    String::from_utf8(name).unwrap()
+                }
+                4 => {
+                    let mut ip = [0; 16];
+                    stream
+                        .read_exact(&mut ip)
+                        .await
+                        .unwrap();
+                    format!("[{}]", std::net::Ipv6Addr::from(ip))
+                }
+                _ => return,
+            };
+            let port = stream.read_u16().await.unwrap();
+            let _ = requested.send(format!("{host}:{port}"));
+
+            let Ok(mut upstream) = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await else {
+                let _ = stream
+                    .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                    .await;
+                return;
+            };
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Client side of the SOCKS5 protocol ([RFC 1928]), with username/password
+//! authentication ([RFC 1929])
+//!
+//! [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928
+//! [RFC 1929]: https://www.rfc-editor.org/rfc/rfc1929
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 0x05;
+const NO_AUTH: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const AUTH_VERSION: u8 = 0x01;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// The destination of a SOCKS5 `CONNECT` request
+pub(super) enum Target<'a> {
+    /// An address resolved by the client
+    Addr(SocketAddr),
+    /// A host name for the proxy to resolve
+    Domain(&'a str, u16),
+}
+
+/// Asks the SOCKS5 proxy connected over `stream` to open a connection to `target`
+pub(super) async fn connect<S>(
+    stream: &mut S,
+    target: Target<'_>,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[VERSION, 2, NO_AUTH, USERNAME_PASSWORD],
+        None => &[VERSION, 1, NO_AUTH],
+    };
+    stream.write_all(greeting).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("unexpected SOCKS version in proxy reply"));
+    }
+    match (reply[1], credentials) {
+        (NO_AUTH, _) => {}
+        (USERNAME_PASSWORD, Some((username, password))) => {
+            authenticate(stream, username, password).await?
+        }
+        (NO_ACCEPTABLE_METHODS, _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS5 proxy accepted none of the offered authentication methods",
+            ))
+        }
+        _ => return Err(invalid_data("SOCKS5 proxy chose an unsupported method")),
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    let port = match target {
+        Target::Addr(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        Target::Domain(host, port) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name too long"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("unexpected SOCKS version in proxy reply"));
+    }
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "SOCKS5 proxy refused connection: {}",
+                reply_message(reply[1])
+            ),
+        ));
+    }
+
+    // Skip the bound address, which is of no use to us
+    let len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(invalid_data("invalid address type in SOCKS5 reply")),
+    };
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn authenticate<S>(stream: &mut S, username: &str, password: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let too_long = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS5 username and password must be at most 255 bytes",
+        )
+    };
+    let username_len = u8::try_from(username.len()).map_err(|_| too_long())?;
+    let password_len = u8::try_from(password.len()).map_err(|_| too_long())?;
+
+    let mut request = vec![AUTH_VERSION, username_len];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password_len);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    match reply[1] {
+        0 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 proxy rejected the credentials",
+        )),
+    }
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rustls::{ClientConnection, HandshakeKind};
+
+/// Counts of the TLS handshakes made by an [`HttpsConnector`](crate::HttpsConnector),
+/// by whether they resumed a session
+///
+/// A hit is a handshake that resumed a session from the session store, a
+/// miss is a full handshake. Failed handshakes are not counted.
+///
+/// See [`HttpsConnector::resumption_stats()`](crate::HttpsConnector::resumption_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResumptionStats {
+    hits: u64,
+    misses: u64,
+}
+
+impl ResumptionStats {
+    /// The number of handshakes that resumed a session
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// The number of full handshakes
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+/// The counters behind [`ResumptionStats`], shared by a connector and its clones
+#[derive(Debug, Default)]
+pub(crate) struct ResumptionCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResumptionCounters {
+    /// Counts the completed handshake of `conn`
+    pub(crate) fn record(&self, conn: &ClientConnection) {
+        let counter = match conn.handshake_kind() {
+            Some(HandshakeKind::Resumed) => &self.hits,
+            Some(HandshakeKind::Full | HandshakeKind::FullWithHelloRetryRequest) => &self.misses,
+            None => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> ResumptionStats {
+        ResumptionStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
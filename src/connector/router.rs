@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use http::Uri;
+use rustls::ClientConfig;
+
+use super::ResolveClientConfig;
+
+/// A [`ResolveClientConfig`] routing destinations to TLS configurations by
+/// host and port
+///
+/// Hosts are either exact names, or wildcard patterns like `*.example.com`,
+/// which match any subdomain of `example.com` (but not `example.com`
+/// itself). Routes can be restricted to a port; the port of URLs without one
+/// is the default port of their scheme.
+///
+/// When several routes match, the most specific one wins: exact hosts come
+/// before wildcards, longer wildcard domains before shorter ones, and routes
+/// with a port before routes without one. Destinations matching no route use
+/// the default configuration if one was set, or the connector's otherwise.
+///
+/// # Examples
+///
+/// ```
+/// # fn doc(internal: rustls::ClientConfig, mtls: rustls::ClientConfig, public: rustls::ClientConfig) {
+/// use hyper_rustls::ClientConfigRouter;
+///
+/// let router = ClientConfigRouter::new()
+///     .with_route("*.internal.example.com", internal)
+///     .with_route_and_port("payments.internal.example.com", 8443, mtls)
+///     .with_default(public);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ClientConfigRouter {
+    routes: Vec<Route>,
+    default: Option<Arc<ClientConfig>>,
+}
+
+impl ClientConfigRouter {
+    /// Creates a router without any routes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `config` for `host`, on any port
+    ///
+    /// `host` is compared case-insensitively, and may be a wildcard pattern
+    /// like `*.example.com`.
+    pub fn with_route(self, host: &str, config: impl Into<Arc<ClientConfig>>) -> Self {
+        self.add_route(host, None, config.into())
+    }
+
+    /// Uses `config` for `host` on `port`
+    ///
+    /// See [`with_route()`](Self::with_route).
+    pub fn with_route_and_port(
+        self,
+        host: &str,
+        port: u16,
+        config: impl Into<Arc<ClientConfig>>,
+    ) -> Self {
+        self.add_route(host, Some(port), config.into())
+    }
+
+    /// Uses `config` for destinations matching no route
+    pub fn with_default(mut self, config: impl Into<Arc<ClientConfig>>) -> Self {
+        self.default = Some(config.into());
+        self
+    }
+
+    fn add_route(mut self, host: &str, port: Option<u16>, config: Arc<ClientConfig>) -> Self {
+        self.routes.push(Route {
+            host: Host::parse(host),
+            port,
+            config,
+        });
+        self
+    }
+}
+
+impl ResolveClientConfig for ClientConfigRouter {
+    fn resolve(&self, uri: &Uri) -> Option<Arc<ClientConfig>> {
+        let host = Host::of(uri);
+        let port = uri
+            .port_u16()
+            .or_else(|| match uri.scheme_str() {
+                Some("https") => Some(443),
+                Some("http") => Some(80),
+                _ => None,
+            });
+
+        self.routes
+            .iter()
+            .filter_map(|route| {
+                let specificity = route.host.matches(&host)?;
+                match route.port {
+                    Some(p) if Some(p) != port => None,
+                    _ => Some(((specificity, route.port.is_some()), route)),
+                }
+            })
+            // Keep the first of equally specific routes
+            .rev()
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, route)| route.config.clone())
+            .or_else(|| self.default.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Route {
+    host: Host,
+    port: Option<u16>,
+    config: Arc<ClientConfig>,
+}
+
+/// A host name pattern, either exact or a wildcard like `*.example.com`
+#[derive(Clone, Debug)]
+pub(super) enum Host {
+    Exact(String),
+    /// The domain of a wildcard pattern, including its leading dot
+    Wildcard(String),
+}
+
+impl Host {
+    /// Parses a pattern, which is compared case-insensitively
+    pub(super) fn parse(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(domain) if domain.starts_with('.') => Self::Wildcard(domain.to_owned()),
+            _ => Self::Exact(pattern),
+        }
+    }
+
+    /// The lowercase host of `uri`, without the brackets of IPv6 addresses
+    pub(super) fn of(uri: &Uri) -> String {
+        let host = uri.host().unwrap_or_default();
+        host.strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host)
+            .to_ascii_lowercase()
+    }
+
+    /// Returns how specific the match is, if the lowercase `host` matches
+    pub(super) fn matches(&self, host: &str) -> Option<usize> {
+        match self {
+            Self::Exact(exact) => (exact == host).then_some(usize::MAX),
+            Self::Wildcard(domain) => host
+                .strip_suffix(domain.as_str())
+                .filter(|label| !label.is_empty())
+                .map(|_| domain.len()),
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_by_specificity() {
+        let [exact, exact_port, wildcard, narrow, default] = configs();
+        let router = ClientConfigRouter::new()
+            .with_route("*.example.com", wildcard.clone())
+            .with_route("*.api.example.com", narrow.clone())
+            .with_route("Exact.Example.com", exact.clone())
+            .with_route_and_port("exact.example.com", 8443, exact_port.clone())
+            .with_default(default.clone());
+
+        let cases = [
+            ("https://exact.example.com", &exact),
+            ("https://EXACT.example.com:443", &exact),
+            ("https://exact.example.com:8443", &exact_port),
+            ("https://www.example.com", &wildcard),
+            ("https://a.b.example.com", &wildcard),
+            ("https://v1.api.example.com", &narrow),
+            ("https://example.com", &default),
+            ("https://notexample.com", &default),
+            ("https://[::1]", &default),
+        ];
+        for (uri, expected) in cases {
+            let resolved = router
+                .resolve(&uri.parse().unwrap())
+                .unwrap();
+            assert!(Arc::ptr_eq(&resolved, expected), "{uri}");
+        }
+    }
+
+    #[test]
+    fn default_ports() {
+        let [https, http, ..] = configs();
+        let router = ClientConfigRouter::new()
+            .with_route_and_port("localhost", 443, https.clone())
+            .with_route_and_port("localhost", 80, http.clone());
+
+        let resolve = |uri: &str| router.resolve(&uri.parse().unwrap());
+        assert!(Arc::ptr_eq(&resolve("https://localhost").unwrap(), &https));
+        assert!(Arc::ptr_eq(&resolve("http://localhost").unwrap(), &http));
+        assert!(resolve("https://localhost:8443").is_none());
+    }
+
+    fn configs() -> [Arc<ClientConfig>; 5] {
+        std::array::from_fn(|_| {
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(rustls::RootCertStore::empty())
+                    .with_no_client_auth(),
+            )
+        })
+    }
+}
@@ -0,0 +1,198 @@
+use std::{fmt, io};
+
+use http::uri::Scheme;
+use http::StatusCode;
+
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors returned by the [`HttpsConnector`](crate::HttpsConnector)
+///
+/// The connector's [`Service::Error`](tower_service::Service::Error) type is
+/// a boxed error; callers can use `downcast_ref::<Error>()` on it to inspect
+/// the failure.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// The destination URI uses a scheme the connector does not handle
+    ///
+    /// This includes `http` when the connector only allows HTTPS.
+    UnsupportedScheme(Scheme),
+    /// The destination URI has no scheme
+    MissingScheme,
+    /// The [`ResolveServerName`](crate::ResolveServerName) failed to produce a server name
+    ServerName(BoxError),
+    /// The underlying connector failed to establish a connection
+    Connect(BoxError),
+    /// Establishing a tunnel through the proxy failed
+    ///
+    /// This covers I/O errors and malformed responses from the proxy.
+    Tunnel(io::Error),
+    /// The proxy refused to open a tunnel, answering `CONNECT` with a non-2xx status
+    ProxyRejected(StatusCode),
+    /// The TLS handshake failed
+    Tls(rustls::Error),
+    /// An I/O error occurred during the TLS handshake
+    Io(io::Error),
+    /// The TLS handshake did not complete within the configured timeout
+    ///
+    /// See [`HttpsConnector::set_handshake_timeout()`](crate::HttpsConnector::set_handshake_timeout).
+    HandshakeTimeout,
+    /// Establishing the QUIC connection or its HTTP/3 session failed
+    ///
+    /// This includes TLS handshake failures, which QUIC reports as
+    /// connection errors.
+    #[cfg(feature = "http3")]
+    Quic(BoxError),
+}
+
+impl Error {
+    /// Classify an error returned by a TLS handshake
+    pub(crate) fn handshake(err: io::Error) -> Self {
+        match err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        {
+            Some(tls) => Self::Tls(tls.clone()),
+            None => Self::Io(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedScheme(scheme) => write!(f, "unsupported scheme {scheme}"),
+            Self::MissingScheme => f.write_str("missing scheme"),
+            Self::ServerName(err) => write!(f, "failed to resolve server name: {err}"),
+            Self::Connect(err) => write!(f, "failed to connect: {err}"),
+            Self::Tunnel(err) => write!(f, "failed to establish proxy tunnel: {err}"),
+            Self::ProxyRejected(status) => write!(f, "proxy rejected CONNECT with status {status}"),
+            Self::Tls(err) => write!(f, "TLS handshake failed: {err}"),
+            Self::Io(err) => write!(f, "I/O error during TLS handshake: {err}"),
+            Self::HandshakeTimeout => f.write_str("TLS handshake timed out"),
+            #[cfg(feature = "http3")]
+            Self::Quic(err) => write!(f, "failed to establish HTTP/3 connection: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ServerName(err) | Self::Connect(err) => Some(&**err),
+            #[cfg(feature = "http3")]
+            Self::Quic(err) => Some(&**err),
+            Self::Tls(err) => Some(err),
+            Self::Io(err) | Self::Tunnel(err) => Some(err),
+            Self::UnsupportedScheme(_)
+            | Self::MissingScheme
+            | Self::ProxyRejected(_)
+            | Self::HandshakeTimeout => None,
+        }
+    }
+}
+
+/// A problem found when configuring an [`HttpsConnectorBuilder`](crate::HttpsConnectorBuilder)
+///
+/// [`HttpsAcceptorBuilder::try_with_tls_config()`](crate::HttpsAcceptorBuilder::try_with_tls_config)
+/// reports [`PresetAlpn`](Self::PresetAlpn) as well.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum BuilderError {
+    /// The TLS configuration already has ALPN protocols
+    ///
+    /// The builder derives them from the enabled HTTP versions. Other
+    /// protocols can be offered with
+    /// [`with_alpn_protocol()`](crate::HttpsConnectorBuilder::with_alpn_protocol).
+    PresetAlpn(Vec<Vec<u8>>),
+    /// HTTP/2 is the only enabled version, but `h2` is not offered via ALPN
+    MissingHttp2Alpn,
+    /// `h2` is offered via ALPN, but HTTP/2 is not enabled
+    UnexpectedHttp2Alpn,
+    /// The crypto provider does not support the required protocol versions
+    IncompatibleProvider(rustls::Error),
+    /// The root certificate store is empty, so no server could be verified
+    ///
+    /// This is checked when the connector is built, for roots configured
+    /// through the builder's shorthands. A [`ClientConfig`](rustls::ClientConfig)
+    /// passed to
+    /// [`with_tls_config()`](crate::HttpsConnectorBuilder::with_tls_config)
+    /// does not expose its roots and is not checked.
+    MissingRoots,
+    /// The certificate verifier could not be initialized
+    Verifier(rustls::Error),
+    /// A setting of the builder cannot be honored by the HTTP/3 connector
+    ///
+    /// See [`enable_http3()`](crate::HttpsConnectorBuilder::enable_http3).
+    #[cfg(feature = "http3")]
+    UnsupportedWithHttp3(&'static str),
+    /// The crypto provider lacks the `TLS13_AES_128_GCM_SHA256` cipher
+    /// suite, which QUIC requires
+    #[cfg(feature = "http3")]
+    MissingQuicCipherSuite,
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PresetAlpn(_) => f.write_str("ALPN protocols should not be pre-defined"),
+            Self::MissingHttp2Alpn => {
+                f.write_str("HTTP/2 is the only enabled version, but h2 is not offered via ALPN")
+            }
+            Self::UnexpectedHttp2Alpn => {
+                f.write_str("h2 is offered via ALPN, but HTTP/2 is not enabled")
+            }
+            Self::IncompatibleProvider(err) => write!(f, "incompatible crypto provider: {err}"),
+            Self::MissingRoots => f.write_str("no root certificates"),
+            Self::Verifier(err) => write!(f, "failed to initialize certificate verifier: {err}"),
+            #[cfg(feature = "http3")]
+            Self::UnsupportedWithHttp3(setting) => {
+                write!(f, "{setting} is not supported over HTTP/3")
+            }
+            #[cfg(feature = "http3")]
+            Self::MissingQuicCipherSuite => {
+                f.write_str("the crypto provider lacks TLS13_AES_128_GCM_SHA256, required by QUIC")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::IncompatibleProvider(err) | Self::Verifier(err) => Some(err),
+            Self::PresetAlpn(_)
+            | Self::MissingHttp2Alpn
+            | Self::UnexpectedHttp2Alpn
+            | Self::MissingRoots => None,
+            #[cfg(feature = "http3")]
+            Self::UnsupportedWithHttp3(_) | Self::MissingQuicCipherSuite => None,
+        }
+    }
+}
+
+/// The problems that prevented building an [`HttpsConnector`](crate::HttpsConnector)
+///
+/// See [`try_build()`](crate::HttpsConnectorBuilder::try_build).
+#[derive(Debug)]
+pub struct BuildError(pub(crate) Vec<BuilderError>);
+
+impl BuildError {
+    /// All the problems found in the configuration
+    pub fn problems(&self) -> &[BuilderError] {
+        &self.0
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid connector configuration")?;
+        for (i, problem) in self.0.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(f, "{separator}{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildError {}
@@ -35,42 +35,71 @@
 #![warn(missing_docs)]
 #![cfg_attr(hyper_rustls_docsrs, feature(doc_cfg))]
 
+mod acceptor;
 mod config;
 mod connector;
+mod error;
+mod spki;
 mod stream;
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod test_util;
 
 #[cfg(feature = "logging")]
 mod log {
-    #[cfg(any(feature = "rustls-native-certs", feature = "webpki-roots"))]
-    pub(crate) use log::debug;
-    #[cfg(feature = "rustls-native-certs")]
-    pub(crate) use log::warn;
+    pub(crate) use log::{debug, warn};
 }
 
 #[cfg(not(feature = "logging"))]
 mod log {
-    #[cfg(any(feature = "rustls-native-certs", feature = "webpki-roots"))]
-    macro_rules! debug    ( ($($tt:tt)*) => {{}} );
-    #[cfg(any(feature = "rustls-native-certs", feature = "webpki-roots"))]
+    macro_rules! debug    ( ($($tt:tt)*) => {{ let _ = format_args!($($tt)*); }} );
     pub(crate) use debug;
-    #[cfg(feature = "rustls-native-certs")]
-    macro_rules! warn_    ( ($($tt:tt)*) => {{}} );
-    #[cfg(feature = "rustls-native-certs")]
+    macro_rules! warn_    ( ($($tt:tt)*) => {{ let _ = format_args!($($tt)*); }} );
     pub(crate) use warn_ as warn;
 }
 
+pub use crate::acceptor::builder::AcceptorBuilder as HttpsAcceptorBuilder;
+pub use crate::acceptor::{HttpsAcceptor, HttpsListener, Incoming};
+pub use crate::config::env::EnvRoots;
+pub use crate::config::identity::ClientIdentity;
+pub use crate::config::pinning::{PinSet, PinningVerifier};
+pub use crate::config::roots::RootsBuilder;
 pub use crate::config::ConfigBuilderExt;
+pub use crate::connector::alt_svc::{
+    AltService, AltSvcCache, AltSvcConnector, AltSvcStore, InMemoryAltSvcStore,
+};
 pub use crate::connector::builder::ConnectorBuilder as HttpsConnectorBuilder;
+pub use crate::connector::client_cert::ClientCertResolver;
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use crate::connector::early_data::{AllowEarlyData, EarlyDataClient};
+pub use crate::connector::ech::{Ech, ResolveEchConfig};
+pub use crate::connector::handle::ClientConfigHandle;
+#[cfg(feature = "http3")]
+pub use crate::connector::http3::{Http3Connection, Http3Connector};
+pub use crate::connector::prior_knowledge::Http2PriorKnowledge;
+pub use crate::connector::proxy::{NoProxy, Proxy};
+pub use crate::connector::resumption::ResumptionStats;
+pub use crate::connector::router::ClientConfigRouter;
 pub use crate::connector::{
-    DefaultServerNameResolver, FixedServerNameResolver, HttpsConnector, ResolveServerName,
+    DefaultServerNameResolver, FixedServerNameResolver, HttpsConnector, ResolveClientConfig,
+    ResolveServerName,
 };
-pub use crate::stream::MaybeHttpsStream;
+pub use crate::error::{BuildError, BuilderError, Error};
+pub use crate::stream::{EarlyData, EarlyDataStatus, HttpsStream, MaybeHttpsStream, TlsInfo};
 
 /// The various states of the [`HttpsConnectorBuilder`]
 pub mod builderstates {
+    #[cfg(feature = "http3")]
+    pub use crate::connector::builder::WantsHttp3;
     #[cfg(feature = "http2")]
     pub use crate::connector::builder::WantsProtocols3;
     pub use crate::connector::builder::{
         WantsProtocols1, WantsProtocols2, WantsSchemes, WantsTlsConfig,
     };
 }
+
+/// The various states of the [`HttpsAcceptorBuilder`]
+pub mod acceptorbuilderstates {
+    #[cfg(feature = "http2")]
+    pub use crate::acceptor::builder::WantsProtocols3;
+    pub use crate::acceptor::builder::{WantsProtocols1, WantsProtocols2, WantsTlsConfig};
+}
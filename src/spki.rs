@@ -0,0 +1,99 @@
+use rustls::crypto::hash::{Hash, HashAlgorithm};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+
+/// Computes the SHA-256 hash of a `SubjectPublicKeyInfo`
+///
+/// `contents` is the encoded structure without its outer `SEQUENCE` header,
+/// as found in [`TrustAnchor::subject_public_key_info`](rustls::pki_types::TrustAnchor).
+/// The header is restored before hashing, so the result matches the usual
+/// SPKI fingerprints (as in `openssl pkey -pubin -outform der | sha256sum`).
+///
+/// Returns `None` if no SHA-256 implementation is available.
+pub(crate) fn sha256(contents: &[u8]) -> Option<[u8; 32]> {
+    let mut der = Vec::with_capacity(contents.len() + 6);
+    der.push(0x30);
+    match u8::try_from(contents.len()) {
+        Ok(len) if len < 0x80 => der.push(len),
+        _ => {
+            let len = contents.len().to_be_bytes();
+            let skip = len
+                .iter()
+                .take_while(|&&b| b == 0)
+                .count();
+            der.push(0x80 | (len.len() - skip) as u8);
+            der.extend_from_slice(&len[skip..]);
+        }
+    }
+    der.extend_from_slice(contents);
+    sha256_der(&der)
+}
+
+/// Computes the SHA-256 hash of a DER-encoded `SubjectPublicKeyInfo`,
+/// including its outer `SEQUENCE` header
+///
+/// Returns `None` if no SHA-256 implementation is available.
+pub(crate) fn sha256_der(der: &[u8]) -> Option<[u8; 32]> {
+    sha256_provider()?
+        .hash(der)
+        .as_ref()
+        .try_into()
+        .ok()
+}
+
+/// The signature verification algorithms of the process-default crypto
+/// provider, or of the provider enabled by crate features
+pub(crate) fn signature_algorithms() -> Option<WebPkiSupportedAlgorithms> {
+    with_provider(|provider| Some(provider.signature_verification_algorithms))
+}
+
+/// Finds a SHA-256 implementation among the cipher suites of the process-default
+/// crypto provider, or of the provider enabled by crate features
+fn sha256_provider() -> Option<&'static dyn Hash> {
+    with_provider(|provider| {
+        provider
+            .cipher_suites
+            .iter()
+            .filter_map(|suite| suite.tls13())
+            .map(|suite| suite.common.hash_provider)
+            .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)
+    })
+}
+
+fn with_provider<T>(f: impl FnOnce(&CryptoProvider) -> Option<T>) -> Option<T> {
+    if let Some(provider) = CryptoProvider::get_default() {
+        return f(provider);
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    return f(&rustls::crypto::aws_lc_rs::default_provider());
+    #[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+    return f(&rustls::crypto::ring::default_provider());
+    #[cfg(not(any(feature = "ring", feature = "aws-lc-rs")))]
+    {
+        let _ = f;
+        None
+    }
+}
+
+#[cfg(all(test, any(feature = "ring", feature = "aws-lc-rs")))]
+mod tests {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    #[test]
+    fn sample_ca_fingerprint() {
+        let ca = CertificateDer::pem_slice_iter(include_bytes!("../examples/sample.pem"))
+            .last()
+            .unwrap()
+            .unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca).unwrap();
+
+        // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum
+        assert_eq!(
+            super::sha256(&roots.roots[0].subject_public_key_info).unwrap(),
+            *b"\xad\x5c\x8d\x1e\x5c\x6e\xd7\x02\xc7\x27\x0b\x07\x97\xae\x4a\x91\
+               \xd9\xc4\xbd\x79\x67\xf5\xf3\x1b\xb7\xf6\xab\x09\xeb\x25\x57\x7f"
+        );
+    }
+}
@@ -2,35 +2,56 @@
 use std::fmt;
 use std::io;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use hyper::rt;
 use hyper_util::client::legacy::connect::{Connected, Connection};
 
 use hyper_util::rt::TokioIo;
+use rustls::client::{ClientConnection, EchStatus};
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{HandshakeKind, ProtocolVersion, SupportedCipherSuite};
 use tokio_rustls::client::TlsStream;
 
 /// A stream that might be protected with TLS.
+///
+/// More kinds of streams may be added in future releases, so matches on this
+/// enum need a wildcard arm.
 #[allow(clippy::large_enum_variant)]
+#[non_exhaustive]
 pub enum MaybeHttpsStream<T> {
     /// A stream over plain text.
     Http(T),
     /// A stream protected with TLS.
-    Https(TokioIo<TlsStream<TokioIo<T>>>),
+    Https(HttpsStream<T>),
+    /// A stream over plain text, tunnelled through a TLS-protected proxy connection.
+    HttpOverTls(HttpsStream<T>),
+    /// A stream over plain text, speaking HTTP/2 with prior knowledge (h2c).
+    H2c(T),
+}
+
+impl<T> MaybeHttpsStream<T> {
+    /// The state of the TLS 1.3 early data sent to the destination, if any
+    ///
+    /// See [`HttpsStream::early_data()`].
+    pub fn early_data(&self) -> Option<EarlyData> {
+        match self {
+            Self::Https(s) => s.early_data(),
+            Self::Http(_) | Self::HttpOverTls(_) | Self::H2c(_) => None,
+        }
+    }
 }
 
 impl<T: rt::Read + rt::Write + Connection + Unpin> Connection for MaybeHttpsStream<T> {
     fn connected(&self) -> Connected {
         match self {
             Self::Http(s) => s.connected(),
-            Self::Https(s) => {
-                let (tcp, tls) = s.inner().get_ref();
-                if tls.alpn_protocol() == Some(b"h2") {
-                    tcp.inner().connected().negotiated_h2()
-                } else {
-                    tcp.inner().connected()
-                }
-            }
+            Self::Https(s) => s.connected(),
+            // The TLS session is with the proxy; don't report it as the destination's
+            Self::HttpOverTls(s) => s.get_ref().0.connected(),
+            Self::H2c(s) => s.connected().negotiated_h2(),
         }
     }
 }
@@ -40,6 +61,8 @@ impl<T: fmt::Debug> fmt::Debug for MaybeHttpsStream<T> {
         match *self {
             Self::Http(..) => f.pad("Http(..)"),
             Self::Https(..) => f.pad("Https(..)"),
+            Self::HttpOverTls(..) => f.pad("HttpOverTls(..)"),
+            Self::H2c(..) => f.pad("H2c(..)"),
         }
     }
 }
@@ -52,7 +75,13 @@ impl<T> From<T> for MaybeHttpsStream<T> {
 
 impl<T> From<TlsStream<TokioIo<T>>> for MaybeHttpsStream<T> {
     fn from(inner: TlsStream<TokioIo<T>>) -> Self {
-        Self::Https(TokioIo::new(inner))
+        Self::Https(HttpsStream::from(inner))
+    }
+}
+
+impl<T> From<HttpsStream<T>> for MaybeHttpsStream<T> {
+    fn from(inner: HttpsStream<T>) -> Self {
+        Self::Https(inner)
     }
 }
 
@@ -66,6 +95,8 @@ impl<T: rt::Read + rt::Write + Unpin> rt::Read for MaybeHttpsStream<T> {
         match Pin::get_mut(self) {
             Self::Http(s) => Pin::new(s).poll_read(cx, buf),
             Self::Https(s) => Pin::new(s).poll_read(cx, buf),
+            Self::HttpOverTls(s) => Pin::new(s).poll_read(cx, buf),
+            Self::H2c(s) => Pin::new(s).poll_read(cx, buf),
         }
     }
 }
@@ -80,6 +111,8 @@ impl<T: rt::Write + rt::Read + Unpin> rt::Write for MaybeHttpsStream<T> {
         match Pin::get_mut(self) {
             Self::Http(s) => Pin::new(s).poll_write(cx, buf),
             Self::Https(s) => Pin::new(s).poll_write(cx, buf),
+            Self::HttpOverTls(s) => Pin::new(s).poll_write(cx, buf),
+            Self::H2c(s) => Pin::new(s).poll_write(cx, buf),
         }
     }
 
@@ -88,6 +121,8 @@ impl<T: rt::Write + rt::Read + Unpin> rt::Write for MaybeHttpsStream<T> {
         match Pin::get_mut(self) {
             Self::Http(s) => Pin::new(s).poll_flush(cx),
             Self::Https(s) => Pin::new(s).poll_flush(cx),
+            Self::HttpOverTls(s) => Pin::new(s).poll_flush(cx),
+            Self::H2c(s) => Pin::new(s).poll_flush(cx),
         }
     }
 
@@ -96,6 +131,8 @@ impl<T: rt::Write + rt::Read + Unpin> rt::Write for MaybeHttpsStream<T> {
         match Pin::get_mut(self) {
             Self::Http(s) => Pin::new(s).poll_shutdown(cx),
             Self::Https(s) => Pin::new(s).poll_shutdown(cx),
+            Self::HttpOverTls(s) => Pin::new(s).poll_shutdown(cx),
+            Self::H2c(s) => Pin::new(s).poll_shutdown(cx),
         }
     }
 
@@ -104,6 +141,8 @@ impl<T: rt::Write + rt::Read + Unpin> rt::Write for MaybeHttpsStream<T> {
         match self {
             Self::Http(s) => s.is_write_vectored(),
             Self::Https(s) => s.is_write_vectored(),
+            Self::HttpOverTls(s) => s.is_write_vectored(),
+            Self::H2c(s) => s.is_write_vectored(),
         }
     }
 
@@ -116,6 +155,371 @@ impl<T: rt::Write + rt::Read + Unpin> rt::Write for MaybeHttpsStream<T> {
         match Pin::get_mut(self) {
             Self::Http(s) => Pin::new(s).poll_write_vectored(cx, bufs),
             Self::Https(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            Self::HttpOverTls(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            Self::H2c(s) => Pin::new(s).poll_write_vectored(cx, bufs),
         }
     }
 }
+
+/// A stream protected with TLS.
+///
+/// Besides the TLS stream itself, this keeps track of the server name
+/// sent during the handshake, so it can be reported in [`TlsInfo`].
+///
+/// The TLS session either runs directly over the underlying stream, or
+/// inside a tunnel through a TLS-protected proxy connection.
+pub struct HttpsStream<T> {
+    inner: Layer<T>,
+    server_name: Option<ServerName<'static>>,
+    early_data: Option<EarlyDataStatus>,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Layer<T> {
+    Direct(TokioIo<TlsStream<TokioIo<T>>>),
+    Proxied(Box<TokioIo<TlsStream<TokioIo<HttpsStream<T>>>>>),
+}
+
+impl<T> HttpsStream<T> {
+    pub(crate) fn new(
+        inner: TlsStream<TokioIo<T>>,
+        server_name: Option<ServerName<'static>>,
+    ) -> Self {
+        Self {
+            inner: Layer::Direct(TokioIo::new(inner)),
+            server_name,
+            early_data: None,
+        }
+    }
+
+    pub(crate) fn proxied(
+        inner: TlsStream<TokioIo<Self>>,
+        server_name: Option<ServerName<'static>>,
+    ) -> Self {
+        Self {
+            inner: Layer::Proxied(Box::new(TokioIo::new(inner))),
+            server_name,
+            early_data: None,
+        }
+    }
+
+    /// Marks the stream as sending early data until its handshake completes
+    pub(crate) fn with_early_data(mut self) -> Self {
+        self.early_data = Some(EarlyDataStatus::default());
+        self
+    }
+
+    /// The state of the TLS 1.3 early data sent on this stream, if any
+    ///
+    /// This is `None` unless the stream was returned by a connector with
+    /// [early data](crate::HttpsConnector::set_early_data) enabled, before
+    /// the handshake completed.
+    pub fn early_data(&self) -> Option<EarlyData> {
+        self.early_data.as_ref()?;
+        let (_, conn) = self.get_ref();
+        Some(match conn.is_handshaking() {
+            true => EarlyData::Pending,
+            false if conn.is_early_data_accepted() => EarlyData::Accepted,
+            false => EarlyData::Rejected,
+        })
+    }
+
+    /// Publishes the outcome of the early data once the handshake completed
+    fn update_early_data(&self) {
+        if let Some(status) = &self.early_data {
+            if status.get() == EarlyData::Pending {
+                if let Some(state) = self.early_data() {
+                    status.set(state);
+                }
+            }
+        }
+    }
+
+    /// Returns a reference to the underlying stream and the rustls connection.
+    ///
+    /// If the connection is tunnelled through a TLS-protected proxy, the
+    /// rustls connection is the one with the destination server.
+    pub fn get_ref(&self) -> (&T, &ClientConnection) {
+        match &self.inner {
+            Layer::Direct(s) => {
+                let (io, conn) = s.inner().get_ref();
+                (io.inner(), conn)
+            }
+            Layer::Proxied(s) => {
+                let (io, conn) = s.inner().get_ref();
+                (io.inner().get_ref().0, conn)
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the underlying stream and the rustls connection.
+    ///
+    /// If the connection is tunnelled through a TLS-protected proxy, the
+    /// rustls connection is the one with the destination server.
+    pub fn get_mut(&mut self) -> (&mut T, &mut ClientConnection) {
+        match &mut self.inner {
+            Layer::Direct(s) => {
+                let (io, conn) = s.inner_mut().get_mut();
+                (io.inner_mut(), conn)
+            }
+            Layer::Proxied(s) => {
+                let (io, conn) = s.inner_mut().get_mut();
+                (io.inner_mut().get_mut().0, conn)
+            }
+        }
+    }
+
+    /// Returns the TLS stream with the proxy, if the connection is tunnelled
+    /// through a TLS-protected proxy.
+    pub fn proxy(&self) -> Option<&Self> {
+        match &self.inner {
+            Layer::Direct(_) => None,
+            Layer::Proxied(s) => Some(s.inner().get_ref().0.inner()),
+        }
+    }
+
+    /// Returns details about the negotiated TLS session.
+    pub fn tls_info(&self) -> TlsInfo {
+        let (_, conn) = self.get_ref();
+        TlsInfo {
+            protocol_version: conn.protocol_version(),
+            cipher_suite: conn.negotiated_cipher_suite(),
+            alpn_protocol: conn.alpn_protocol().map(<[u8]>::to_vec),
+            server_name: self.server_name.clone(),
+            peer_certificates: conn.peer_certificates().map(Arc::from),
+            handshake_kind: conn.handshake_kind(),
+            ech_status: conn.ech_status(),
+        }
+    }
+}
+
+impl<T: Connection> HttpsStream<T> {
+    fn connected(&self) -> Connected {
+        let (io, conn) = self.get_ref();
+        let mut connected = io.connected().extra(self.tls_info());
+        if let Some(status) = &self.early_data {
+            connected = connected.extra(status.clone());
+        }
+        match conn.alpn_protocol() {
+            Some(b"h2") => connected.negotiated_h2(),
+            _ => connected,
+        }
+    }
+}
+
+impl<T> fmt::Debug for HttpsStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpsStream")
+            .field("server_name", &self.server_name)
+            .field("proxy", &self.proxy())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> From<TlsStream<TokioIo<T>>> for HttpsStream<T> {
+    fn from(inner: TlsStream<TokioIo<T>>) -> Self {
+        Self::new(inner, None)
+    }
+}
+
+impl<T: rt::Read + rt::Write + Unpin> rt::Read for HttpsStream<T> {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: rt::ReadBufCursor<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let this = Pin::get_mut(self);
+        let result = match &mut this.inner {
+            Layer::Direct(s) => Pin::new(s).poll_read(cx, buf),
+            Layer::Proxied(s) => Pin::new(&mut **s).poll_read(cx, buf),
+        };
+        this.update_early_data();
+        result
+    }
+}
+
+impl<T: rt::Write + rt::Read + Unpin> rt::Write for HttpsStream<T> {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::get_mut(self);
+        let result = match &mut this.inner {
+            Layer::Direct(s) => Pin::new(s).poll_write(cx, buf),
+            Layer::Proxied(s) => Pin::new(&mut **s).poll_write(cx, buf),
+        };
+        this.update_early_data();
+        result
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::get_mut(self);
+        let result = match &mut this.inner {
+            Layer::Direct(s) => Pin::new(s).poll_flush(cx),
+            Layer::Proxied(s) => Pin::new(&mut **s).poll_flush(cx),
+        };
+        this.update_early_data();
+        result
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match &mut Pin::get_mut(self).inner {
+            Layer::Direct(s) => Pin::new(s).poll_shutdown(cx),
+            Layer::Proxied(s) => Pin::new(&mut **s).poll_shutdown(cx),
+        }
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        match &self.inner {
+            Layer::Direct(s) => s.is_write_vectored(),
+            Layer::Proxied(s) => s.is_write_vectored(),
+        }
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::get_mut(self);
+        let result = match &mut this.inner {
+            Layer::Direct(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            Layer::Proxied(s) => Pin::new(&mut **s).poll_write_vectored(cx, bufs),
+        };
+        this.update_early_data();
+        result
+    }
+}
+
+/// Details about a negotiated TLS session.
+///
+/// This is attached to each connection's [`Connected`] info by
+/// [`HttpsConnector`](crate::HttpsConnector), so that hyper makes it available
+/// in the extensions of every response received over that connection:
+///
+/// ```
+/// # fn inspect<B>(response: &http::Response<B>) {
+/// if let Some(info) = response.extensions().get::<hyper_rustls::TlsInfo>() {
+///     println!("negotiated {:?}", info.cipher_suite());
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    protocol_version: Option<ProtocolVersion>,
+    cipher_suite: Option<SupportedCipherSuite>,
+    alpn_protocol: Option<Vec<u8>>,
+    server_name: Option<ServerName<'static>>,
+    peer_certificates: Option<Arc<[CertificateDer<'static>]>>,
+    handshake_kind: Option<HandshakeKind>,
+    ech_status: EchStatus,
+}
+
+impl TlsInfo {
+    /// The negotiated TLS protocol version.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+
+    /// The negotiated cipher suite.
+    pub fn cipher_suite(&self) -> Option<SupportedCipherSuite> {
+        self.cipher_suite
+    }
+
+    /// The protocol agreed via ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The server name sent in the SNI extension.
+    ///
+    /// This is `None` if the connection was made to an IP address, or if SNI
+    /// is disabled in the [`ClientConfig`](rustls::ClientConfig).
+    pub fn server_name(&self) -> Option<&ServerName<'static>> {
+        self.server_name.as_ref()
+    }
+
+    /// The certificate chain presented by the server, end-entity certificate first.
+    pub fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        self.peer_certificates.as_deref()
+    }
+
+    /// Whether the session was resumed rather than established with a full handshake.
+    pub fn is_resumed(&self) -> bool {
+        self.handshake_kind == Some(HandshakeKind::Resumed)
+    }
+
+    /// The kind of handshake that established the session.
+    pub fn handshake_kind(&self) -> Option<HandshakeKind> {
+        self.handshake_kind
+    }
+
+    /// Whether Encrypted Client Hello was offered, and accepted by the server.
+    ///
+    /// See [`Ech`](crate::Ech).
+    pub fn ech_status(&self) -> EchStatus {
+        self.ech_status
+    }
+}
+
+/// The state of the TLS 1.3 early data (0-RTT) sent on a connection
+///
+/// See [`HttpsConnector::set_early_data()`](crate::HttpsConnector::set_early_data).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EarlyData {
+    /// The handshake has not completed yet
+    Pending,
+    /// The server accepted the early data
+    Accepted,
+    /// The server rejected the early data
+    ///
+    /// The data was sent again after the handshake, so the request was
+    /// processed only once.
+    Rejected,
+}
+
+/// The state of the early data sent on a connection, as it progresses
+///
+/// This is attached to the [`Connected`] info of connections that sent early
+/// data, so that hyper makes it available in the extensions of every
+/// response received over them. By the time a response is received, the
+/// handshake has completed:
+///
+/// ```
+/// # fn inspect<B>(response: &http::Response<B>) {
+/// use hyper_rustls::{EarlyData, EarlyDataStatus};
+///
+/// if let Some(status) = response.extensions().get::<EarlyDataStatus>() {
+///     assert_ne!(status.get(), EarlyData::Pending);
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EarlyDataStatus(Arc<AtomicU8>);
+
+impl EarlyDataStatus {
+    /// The current state
+    pub fn get(&self) -> EarlyData {
+        match self.0.load(Ordering::Acquire) {
+            0 => EarlyData::Pending,
+            1 => EarlyData::Accepted,
+            _ => EarlyData::Rejected,
+        }
+    }
+
+    fn set(&self, state: EarlyData) {
+        let value = match state {
+            EarlyData::Pending => 0,
+            EarlyData::Accepted => 1,
+            EarlyData::Rejected => 2,
+        };
+        self.0.store(value, Ordering::Release);
+    }
+}
@@ -0,0 +1,136 @@
+//! Helpers shared by the unit tests
+#![allow(dead_code, reason = "each test module uses a subset of the helpers")]
+
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use hyper_util::client::legacy::connect::Connection;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{MaybeHttpsStream, TlsInfo};
+
+/// The sample certificate chain, ending with its root
+pub(crate) fn sample_certs() -> Vec<CertificateDer<'static>> {
+    CertificateDer::pem_slice_iter(include_bytes!("../examples/sample.pem"))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
+/// The private key of the sample certificate
+pub(crate) fn sample_key() -> PrivateKeyDer<'static> {
+    PrivateKeyDer::from_pem_slice(include_bytes!("../examples/sample.rsa")).unwrap()
+}
+
+/// A root store holding the root of the sample certificate chain
+pub(crate) fn sample_roots() -> rustls::RootCertStore {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(sample_certs().pop().unwrap())
+        .unwrap();
+    roots
+}
+
+/// Trusts the root of the sample certificate chain
+pub(crate) fn trusting_config() -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_root_certificates(sample_roots())
+        .with_no_client_auth()
+}
+
+/// Serves the sample certificate
+pub(crate) fn server_config() -> rustls::ServerConfig {
+    rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(sample_certs(), sample_key())
+        .unwrap()
+}
+
+/// Accepts TLS with the sample certificate, offering `alpn_protocols`
+pub(crate) fn sample_acceptor(alpn_protocols: Vec<Vec<u8>>) -> TlsAcceptor {
+    let mut config = server_config();
+    config.alpn_protocols = alpn_protocols;
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// Serves TLS with the sample certificate, greeting every client with `ok`
+pub(crate) async fn tls_server(alpn_protocols: Vec<Vec<u8>>) -> SocketAddr {
+    tls_server_with(sample_acceptor(alpn_protocols)).await
+}
+
+/// Like [`tls_server()`], accepting TLS with `acceptor`
+pub(crate) async fn tls_server_with(acceptor: TlsAcceptor) -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(mut tls) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let _ = tls.write_all(b"ok").await;
+                let _ = tls.flush().await;
+                let mut buf = [0; 1];
+                let _ = tls.read(&mut buf).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Accepts TCP connections but never sends anything back
+pub(crate) async fn silent_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            held.push(stream);
+        }
+    });
+    (addr, server)
+}
+
+/// The [`TlsInfo`] attached to the connection info of `stream`
+pub(crate) fn connected_tls_info(stream: &MaybeHttpsStream<TokioIo<TcpStream>>) -> TlsInfo {
+    let mut extensions = http::Extensions::new();
+    stream
+        .connected()
+        .get_extras(&mut extensions);
+    extensions
+        .remove::<TlsInfo>()
+        .expect("TlsInfo missing from connection extras")
+}
+
+/// A directory that is removed when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("hyper-rustls-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}